@@ -0,0 +1,239 @@
+// apg: Acceleration plethysmogram (second derivative) wave analysis
+//
+// The second derivative of a pulse wave shows five characteristic waves:
+//   a: early systolic positive wave, on the upstroke
+//   b: early systolic negative wave
+//   c: late systolic re-increasing wave
+//   d: late systolic re-decreasing wave
+//   e: early diastolic positive wave, around the dicrotic notch
+// The ratios b/a and d/a are used in the literature as vascular aging indices.
+//
+// hr_alg3 only sees about 200ms past the start of its above window when it
+// reports a beat, but e comes a few hundred ms after the foot.  So samples are
+// kept in a short history here, `beat` schedules analysis of the pulse starting
+// at its foot, and `push` returns the result once the whole pulse has arrived.
+// Above 100 BPM the next beat comes before that, so a few beats are queued.
+
+use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
+
+const DECIMATE: usize = 4; // Analyse at 250Hz, plenty for the APG
+const HISTORY: usize = 256; // Decimated samples kept, about 1s
+const PULSE_LEN: usize = 150; // Decimated samples analysed from the foot, 600ms
+const PENDING: usize = 4; // Beats waiting for their pulse to arrive, enough past 200 BPM
+const A_SEARCH: usize = 50; // a is looked for in the first 200ms
+const SMOOTH: usize = 5; // Moving average width, before and after differentiating
+const STEP: usize = 2; // Second difference step, decimated samples
+
+// Fiducial point: amplitude of the APG and time after the foot (ms)
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Wave {
+    pub amp: f64,
+    pub ms: usize,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ApgWaves {
+    pub peak_n: usize, // Sample number of the beat's peak, to pair with the beat event
+    pub a: Wave,
+    pub b: Wave,
+    pub c: Wave,
+    pub d: Wave,
+    pub e: Wave,
+}
+
+impl ApgWaves {
+    pub fn b_a(&self) -> f64 {
+        self.b.amp / self.a.amp
+    }
+    pub fn d_a(&self) -> f64 {
+        self.d.amp / self.a.amp
+    }
+}
+
+pub struct Apg {
+    history: ConstGenericRingBuffer<f64, HISTORY>,
+    sum: f64, // Accumulates DECIMATE samples
+    // Sample numbers of the foot and peak of pulses still to arrive, oldest first
+    pending: ConstGenericRingBuffer<(usize, usize), PENDING>,
+}
+
+impl Apg {
+    pub fn new() -> Apg {
+        Apg {
+            history: ConstGenericRingBuffer::<f64, HISTORY>::new(),
+            sum: 0.0,
+            pending: ConstGenericRingBuffer::<(usize, usize), PENDING>::new(),
+        }
+    }
+    // Schedule analysis of the pulse whose foot and peak are at these sample numbers
+    //   Queued behind any still pending; if the queue is full, the oldest is dropped
+    pub fn beat(&mut self, foot_n: usize, peak_n: usize) {
+        self.pending.push((foot_n, peak_n));
+    }
+    // Drop any pending analysis, eg. because of crazy samples
+    pub fn cancel(&mut self) {
+        self.pending.clear();
+    }
+    // Feed sample number n
+    // Return the APG waves when a scheduled pulse has been completely seen, and
+    //   all five waves were found
+    pub fn push(&mut self, n: usize, x: f64) -> Option<ApgWaves> {
+        self.sum += x;
+        if (n + 1) % DECIMATE != 0 {
+            return None;
+        }
+        self.history.push(self.sum / DECIMATE as f64);
+        self.sum = 0.0;
+        let (foot_n, peak_n) = match self.pending.peek() {
+            Some(&(foot_n, peak_n)) if n >= foot_n + PULSE_LEN * DECIMATE => (foot_n, peak_n),
+            _ => return None,
+        };
+        self.pending.dequeue();
+        // Index of the foot in history: history[len - 1] ends at sample n
+        let back = (n - foot_n) / DECIMATE + 1;
+        if back > self.history.len() {
+            return None;
+        }
+        let start = self.history.len() - back;
+        let mut pulse = [0f64; PULSE_LEN];
+        for (p, h) in pulse.iter_mut().zip(self.history.iter().skip(start)) {
+            *p = *h;
+        }
+        let mut waves = analyse(&pulse)?;
+        waves.peak_n = peak_n;
+        Some(waves)
+    }
+}
+
+// Moving average of width SMOOTH, edges use the samples available
+fn smooth(x: &[f64; PULSE_LEN]) -> [f64; PULSE_LEN] {
+    let mut y = [0f64; PULSE_LEN];
+    let h = SMOOTH / 2;
+    for (i, v) in y.iter_mut().enumerate() {
+        let lo = i.saturating_sub(h);
+        let hi = (i + h + 1).min(PULSE_LEN);
+        *v = x[lo..hi].iter().sum::<f64>() / (hi - lo) as f64;
+    }
+    y
+}
+
+// Find the a..e waves in one pulse, starting at its foot
+pub fn analyse(pulse: &[f64; PULSE_LEN]) -> Option<ApgWaves> {
+    let s = smooth(pulse);
+    let mut d2 = [0f64; PULSE_LEN];
+    for i in STEP..PULSE_LEN - STEP {
+        d2[i] = s[i + STEP] - 2.0 * s[i] + s[i - STEP];
+    }
+    let d2 = smooth(&d2);
+    let wave = |i: usize| Wave {
+        amp: d2[i],
+        ms: i * DECIMATE,
+    };
+
+    // a: largest value early in the pulse; the rest are alternating local
+    //   extremes after it
+    let mut ia = STEP;
+    for i in STEP..A_SEARCH {
+        if d2[i] > d2[ia] {
+            ia = i;
+        }
+    }
+    if d2[ia] <= 0.0 {
+        return None;
+    }
+    let ib = next_extreme(&d2, ia, false)?;
+    let ic = next_extreme(&d2, ib, true)?;
+    let id = next_extreme(&d2, ic, false)?;
+    let ie = next_extreme(&d2, id, true)?;
+    Some(ApgWaves {
+        peak_n: 0,
+        a: wave(ia),
+        b: wave(ib),
+        c: wave(ic),
+        d: wave(id),
+        e: wave(ie),
+    })
+}
+
+// Index of the first local maximum (or minimum) after `from`
+fn next_extreme(d2: &[f64; PULSE_LEN], from: usize, max: bool) -> Option<usize> {
+    (from + 1..PULSE_LEN - STEP - 1).find(|&i| {
+        if max {
+            d2[i] > d2[i - 1] && d2[i] >= d2[i + 1]
+        } else {
+            d2[i] < d2[i - 1] && d2[i] <= d2[i + 1]
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::exp;
+
+    // Systolic and diastolic gaussians, times in ms from the foot
+    fn pulse(t: f64, diastolic: f64) -> f64 {
+        let g = |t0: f64, w: f64| exp(-((t - t0) / w) * ((t - t0) / w));
+        600.0 * g(150.0, 60.0) + diastolic * 600.0 * g(350.0, 60.0)
+    }
+
+    #[test]
+    fn synthetic_pulse() {
+        let mut p = [0f64; PULSE_LEN];
+        for (i, v) in p.iter_mut().enumerate() {
+            *v = pulse((i * DECIMATE) as f64, 0.4);
+        }
+        let w = analyse(&p).unwrap();
+        assert!(w.a.ms < w.b.ms && w.b.ms < w.c.ms && w.c.ms < w.d.ms && w.d.ms < w.e.ms);
+        // b sits on the systolic peak, d on the diastolic one
+        assert!((w.b.ms as i32 - 150).abs() <= 12);
+        assert!((w.d.ms as i32 - 350).abs() <= 12);
+        // For a gaussian, b/a = -1 / (2 exp(-3/2)) = -2.24, and d/a is that
+        //   scaled by the relative size of the diastolic wave
+        assert!(w.b_a() < -1.9 && w.b_a() > -2.6, "b/a={}", w.b_a());
+        assert!(w.d_a() < -0.6 && w.d_a() > -1.2, "d/a={}", w.d_a());
+    }
+
+    #[test]
+    fn streaming() {
+        let mut apg = Apg::new();
+        let mut found = None;
+        for n in 0..3000 {
+            // Pulse every 800ms, feet at 0, 800, 1600...
+            let x = 32768.0 + pulse((n % 800) as f64, 0.4);
+            if n == 1610 {
+                apg.beat(1600, 1750);
+            }
+            if let Some(w) = apg.push(n, x) {
+                found = Some((n, w));
+            }
+        }
+        let (n, w) = found.unwrap();
+        assert_eq!(n, 1600 + PULSE_LEN * DECIMATE + DECIMATE - 1);
+        assert_eq!(w.peak_n, 1750);
+        assert!(w.b_a() < -1.9 && w.b_a() > -2.6);
+    }
+
+    #[test]
+    fn fast_rate() {
+        // 150 BPM: each pulse overlaps the tail of the one before, and the next
+        //   beat is reported well before the 600ms from its foot have arrived
+        let mut apg = Apg::new();
+        let mut peaks = heapless::Vec::<usize, 16>::new();
+        for n in 0..6000 {
+            let t = (n % 400) as f64;
+            let x = 32768.0 + pulse(t, 0.4) + pulse(t + 400.0, 0.4);
+            if n % 400 == 160 {
+                let foot = n - 160;
+                apg.beat(foot, foot + 150);
+            }
+            if let Some(w) = apg.push(n, x) {
+                assert!((w.b.ms as i32 - 150).abs() <= 12, "{:?}", w);
+                _ = peaks.push(w.peak_n);
+            }
+        }
+        // Every beat but the ones still arriving at the end is analysed
+        assert_eq!(peaks.len(), 14);
+        assert!(peaks.iter().enumerate().all(|(k, p)| *p == 150 + 400 * k));
+    }
+}
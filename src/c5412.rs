@@ -1,184 +1,707 @@
-// Parallel 7-segment driver for C5412 (legacy) LED
+// Parallel 14-segment driver for C5412 (legacy) LED
 // C5412 is a 2-digit 14-segment, common cathode LED similar to
 // https://www.luckylight.cn/media/component/data-sheet/KWA-541CVB.pdf
 //
-// There is no custom constructor, you just populate the GPIOs you're using into
-// C5412Pins: 8 each for the 2 common cathodes (p11..p18, p21..p28) and 8 for
-// the 7 segments (the center horizontal is split into 2 segments)
+// C5412Pins::new takes the GPIOs you're using: 8 for the common cathode of
+// each of the DIGITS digits, left to right, and 14 for the segments a..n:
+//
+//      ---a---
+//     |\  |  /|
+//     f g h i b
+//     |  \|/  |
+//      -j- -n-
+//     |  /|\  |
+//     e k l m c
+//     |/  |  \|
+//      ---d---
+//
+// It checks them with a PinMapBuilder, which catches pins used twice or
+// outside GPIOA..GPIOG, and can report which ports are used.  More digits (a
+// third digit, or a second C5412) share the segment pins, and just need DIGITS
+// changed and their cathode pins added.
+//
+// Other tasks talk to the display through C5412Display, which implements the
+// Display trait by rendering into Frames, using the font in glyph.rs, and
+// signalling process() whenever they change.  Numbers too big for the digits
+// are not cut off: the leading digits and the rest are shown alternately, eg. a
+// 2 digit display shows 120 as "1 " then "20".
+//
+// The multiplexing itself is timed by hardware: process() works out each
+// refresh ahead of time as a schedule of phases (each digit on, then all off),
+// each phase being BSRR words for the GPIO ports and how long to hold it.  The
+// TIM7 interrupt then writes a phase's words and sets the timer for the next
+// one, so the PWM stays steady however long other tasks hold up the executor.
+// A digit's ganged cathodes can span ports (on the board, digit 0's are on
+// GPIOD..GPIOF and digit 1's on GPIOE..GPIOG), so they can't all switch at
+// once.  Instead each phase turns segments off, then switches cathodes, then
+// turns segments on, one write per port for each, so cathodes only switch
+// while no segment is lit, and the first few never carry the current meant
+// for all 8.
+// A late schedule just means the last one is shown again.  Interrupts that
+// start late are counted as overruns, as a health metric.
+//
+// Anything that moves is sequenced by process() itself, once per refresh:
+//   * At boot, a lamp test lights each segment of each digit in turn, to check
+//     the wiring, then the firmware version scrolls by, then a check mark
+//     shows it's ready.  The lamp test can be rerun at any time.
+//   * Text too long for the digits scrolls through them, marquee style.  If
+//     the same text comes back, eg. after an alarm alternating with it, it
+//     carries on from where it would have got to rather than starting over
+//   * The spinner, while searching for a pulse, runs a segment around the
+//     outside of all the digits
+//   * Each beat briefly lights BEAT_GLYPH on the last digit, as the decimal
+//     points aren't wired up
 //
 // This driver is designed for no series resistances, so it expects to PWM even
 // pins that are "on" all the time to avoid frying the display.
 //
+// Brightness is one of LEVELS steps of on time, from a gamma corrected table
+// so each step looks about as much brighter as the last.  The top step is the
+// original fixed 2ms on in every 7ms, with no current limit data to justify
+// going any higher; the rest are dimmer.  The 8 ganged cathode
+// pins of a digit can only sink so much, so the more segments are lit, the
+// less current each gets: each digit's on time is scaled by how many of its
+// segments are lit, so an "8" looks as bright as a "1".
+//
 // Turns out during debugging the display didn't fry anyway, because I think the
 // STM32H7 doesn't drive its GPIO that hard, but still, we should be more
 // circumspect.
 
+use core::cell::RefCell;
 use core::sync::atomic::{AtomicU32, Ordering};
 
-use embassy_stm32::gpio::AnyPin;
-use embassy_stm32::gpio::Level::{High, Low};
+use embassy_stm32::gpio::{AnyPin, Level, Output, Pin, Speed};
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::{InterruptExt, Priority};
+use embassy_stm32::{pac, peripherals};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
 use embassy_time::{Instant, Timer};
+use heapless::{String, Vec};
+
+use crate::display::{self, Display, Status, TEXT_CHARS};
+use crate::glyph::{self, Glyph, Segments};
+
+pub const DIGITS: usize = 2;
+const CATHODE_PINS: usize = 8; // Per digit, ganged for current capacity
+const FRAME_US: u64 = 14000; // All digits once, about 70Hz
+const PERIOD_US: u64 = FRAME_US / DIGITS as u64; // On plus off time for each digit
+pub const LEVELS: usize = 8;
+// On time of each brightness level, in 1/1000ths of the period, with all
+//   segments lit: MAX_DUTY * ((level + 1) / LEVELS)^2.2, so even level 0 is lit
+const DUTY: [u64; LEVELS] = [3, 14, 33, 62, 102, 152, 213, 285];
+const MAX_DUTY: u64 = 285; // Just under 2/7, the on time the display was first driven at
+const _: () = assert!(DUTY[LEVELS - 1] <= MAX_DUTY);
+const SHARE: u64 = 6; // Segments' worth of current the cathodes sink before sharing it
+const MIN_PHASE_US: u64 = 30; // Longer than LATE_US, so being that late is harmless
+const PHASES: usize = 2 * DIGITS; // Each digit on, then off
+const LATE_US: u32 = 20; // Interrupts later than this count as an overrun
+const TIMER_HZ: u32 = 64_000_000; // TIM7's clock, APB1 with the default clocks
+const PORTS: usize = 7; // GPIOA..GPIOG
+const GPIOS: [pac::gpio::Gpio; PORTS] = [
+    pac::GPIOA,
+    pac::GPIOB,
+    pac::GPIOC,
+    pac::GPIOD,
+    pac::GPIOE,
+    pac::GPIOF,
+    pac::GPIOG,
+];
+const OVERFLOW_MS: u64 = 500; // Alternation of the two halves of a big number
+const SPINNER_MS: u64 = 80; // Time per spinner step
+const BEAT_MS: u64 = 100; // Beat indicator stays on this long
+const BEAT_GLYPH: Glyph = glyph::L; // Not used by any digit
+const NO_SIGNAL_TEXT: &str = "PLACE FINGER";
+const LAMP_MS: u64 = 120; // Time per segment of the lamp test
+const LAMP_STEPS: u64 = (DIGITS * glyph::SEGMENTS) as u64;
+const VERSION: &str = concat!("V", env!("CARGO_PKG_VERSION"));
+const VERSION_SCROLL_MS: u64 = 250;
+const READY_MS: u64 = 1000;
 
 // Async communication with other threads
 static COUNT_ATOMIC: AtomicU32 = AtomicU32::new(0); // Profiling: # of refreshes so far
 static OVERRUN_ATOMIC: AtomicU32 = AtomicU32::new(0); // Profiling: # of overruns
+static BRIGHTNESS_ATOMIC: AtomicU32 = AtomicU32::new(LEVELS as u32 - 1); // 0..LEVELS-1
+static BEAT_ATOMIC: AtomicU32 = AtomicU32::new(0); // # of beats so far
+static LAMP_TEST_ATOMIC: AtomicU32 = AtomicU32::new(0); // # of lamp tests asked for
+
+// What process() is to show, and how to animate it
+#[derive(Clone, PartialEq, Debug)]
+pub enum Frames {
+    Still([Glyph; DIGITS]),
+    Marquee(Vec<Glyph, TEXT_CHARS>, u64), // Scrolls one character per this many ms
+    Spinner,
+}
+
+pub type FrameSignal = Signal<CriticalSectionRawMutex, Frames>;
+
+pub struct C5412Display {
+    frames: &'static FrameSignal,
+    shown: Frames,
+    scroll_ms: u64,
+}
+
+impl C5412Display {
+    // frames: shared with process(), which shows what is signalled there
+    // scroll_ms: time per character of scrolling text
+    pub fn new(frames: &'static FrameSignal, scroll_ms: u64) -> C5412Display {
+        C5412Display {
+            frames,
+            shown: Frames::Still([glyph::BLANK; DIGITS]),
+            scroll_ms,
+        }
+    }
+    // Pass f to process(), if it isn't what it is showing already, so that
+    //   animations only restart when the content changes
+    fn show(&mut self, f: Frames) {
+        if f != self.shown {
+            self.frames.signal(f.clone());
+            self.shown = f;
+        }
+    }
+}
+
+// Return the glyphs for the first DIGITS characters of s
+fn text_glyphs(s: &str) -> [Glyph; DIGITS] {
+    let mut glyphs = [glyph::BLANK; DIGITS];
+    for (g, ch) in glyphs.iter_mut().zip(s.chars()) {
+        *g = glyph::font(ch);
+    }
+    glyphs
+}
+
+// Return the glyphs for n, right aligned, at ms since boot
+// If n doesn't fit, show its leading digits left aligned for OVERFLOW_MS, then
+//   the rest zero padded, eg. 105 on 2 digits is "1 " then "05"
+fn number_glyphs(n: u32, ms: u64) -> [Glyph; DIGITS] {
+    let fits = 10u32.pow(DIGITS as u32);
+    let s: String<12> = if n < fits {
+        display::format(format_args!("{:>w$}", n, w = DIGITS))
+    } else if (ms / OVERFLOW_MS) % 2 == 0 {
+        display::format(format_args!("{:<w$}", n / fits, w = DIGITS))
+    } else {
+        display::format(format_args!("{:0w$}", n % fits, w = DIGITS))
+    };
+    text_glyphs(&s)
+}
+
+// Return the glyphs for step of scrolling text through the digits
+// Text enters from the right, and leaves on the left before it repeats
+fn marquee_glyphs(text: &[Glyph], step: usize) -> [Glyph; DIGITS] {
+    let mut glyphs = [glyph::BLANK; DIGITS];
+    let pos = step % (text.len() + DIGITS);
+    for (i, g) in glyphs.iter_mut().enumerate() {
+        if let Some(ix) = (pos + i).checked_sub(DIGITS) {
+            *g = text.get(ix).copied().unwrap_or(glyph::BLANK);
+        }
+    }
+    glyphs
+}
+
+// Return the glyphs for step of the spinner: one outside segment at a time,
+//   clockwise around all the digits
+fn spinner_glyphs(step: usize) -> [Glyph; DIGITS] {
+    let mut glyphs = [glyph::BLANK; DIGITS];
+    let last = DIGITS - 1;
+    let mut i = step % (2 * DIGITS + 4);
+    if i < DIGITS {
+        glyphs[i] = glyph::A; // Along the top
+        return glyphs;
+    }
+    i -= DIGITS;
+    match i {
+        0 => glyphs[last] = glyph::B,
+        1 => glyphs[last] = glyph::C,
+        _ if i < DIGITS + 2 => glyphs[last + 2 - i] = glyph::D, // Back along the bottom
+        _ if i == DIGITS + 2 => glyphs[0] = glyph::E,
+        _ => glyphs[0] = glyph::F,
+    }
+    glyphs
+}
+
+// Return the on time for a digit showing g at brightness level
+// Per segment current goes roughly as 1 / (SHARE + lit segments), so scale by
+//   the inverse, which is longest with every segment lit
+fn on_time_us(level: usize, g: Glyph) -> u64 {
+    let lit = g.count_ones() as u64;
+    PERIOD_US * DUTY[level] * (SHARE + lit) / ((SHARE + glyph::SEGMENTS as u64) * 1000)
+}
+
+// Return the "ready" glyphs: a check mark across the last 2 digits, or
+//   squeezed into one if that's all there is
+fn ready_glyphs() -> [Glyph; DIGITS] {
+    let mut glyphs = [glyph::BLANK; DIGITS];
+    match &mut glyphs[..] {
+        [.., short, long] => {
+            *short = glyph::M;
+            *long = glyph::K | glyph::I;
+        }
+        [only] => *only = glyph::L | glyph::I,
+        [] => {}
+    }
+    glyphs
+}
+
+// Works out the glyphs to show at any time from the latest Frames
+struct Sequencer {
+    frames: Frames,
+    start_ms: u64,   // When frames started
+    marquee: Frames, // Last marquee shown, if any
+    marquee_ms: u64, // When it started
+    beat_ms: Option<u64>,
+    lamp_test: Option<(u64, bool)>, // When it started, and whether to follow with the splash
+    version: Vec<Glyph, TEXT_CHARS>,
+}
+
+impl Sequencer {
+    fn new() -> Sequencer {
+        Sequencer {
+            frames: Frames::Still([glyph::BLANK; DIGITS]),
+            start_ms: 0,
+            marquee: Frames::Spinner,
+            marquee_ms: 0,
+            beat_ms: None,
+            lamp_test: None,
+            version: VERSION.chars().map(glyph::font).collect(),
+        }
+    }
+    // Run the lamp test from ms, then the boot splash if splash
+    // Frames set meanwhile are shown once it's done
+    fn lamp_test(&mut self, ms: u64, splash: bool) {
+        self.lamp_test = Some((ms, splash));
+    }
+    // Return the glyphs for the lamp test and splash at ms, if still going
+    fn lamp_test_glyphs(&self, ms: u64) -> Option<[Glyph; DIGITS]> {
+        let (start_ms, splash) = self.lamp_test?;
+        let step = (ms - start_ms) / LAMP_MS;
+        if step < LAMP_STEPS {
+            let mut glyphs = [glyph::BLANK; DIGITS];
+            let seg = step as usize % glyph::SEGMENTS;
+            glyphs[step as usize / glyph::SEGMENTS] = 1 << seg;
+            return Some(glyphs);
+        }
+        if !splash {
+            return None;
+        }
+        let t = ms - start_ms - LAMP_STEPS * LAMP_MS;
+        let version_steps = (self.version.len() + DIGITS) as u64;
+        if t < version_steps * VERSION_SCROLL_MS {
+            return Some(marquee_glyphs(&self.version, (t / VERSION_SCROLL_MS) as usize));
+        }
+        if t < version_steps * VERSION_SCROLL_MS + READY_MS {
+            return Some(ready_glyphs());
+        }
+        None
+    }
+    // Start showing frames at ms, or resume them if they're the last marquee
+    fn set(&mut self, frames: Frames, ms: u64) {
+        self.start_ms = ms;
+        if let Frames::Marquee(..) = frames {
+            if frames != self.marquee {
+                self.marquee = frames.clone();
+                self.marquee_ms = ms;
+            }
+            self.start_ms = self.marquee_ms;
+        }
+        self.frames = frames;
+    }
+    fn beat(&mut self, ms: u64) {
+        self.beat_ms = Some(ms);
+    }
+    // Return the glyphs to show at ms
+    fn glyphs(&self, ms: u64) -> [Glyph; DIGITS] {
+        if let Some(glyphs) = self.lamp_test_glyphs(ms) {
+            return glyphs;
+        }
+        let t = ms - self.start_ms;
+        match &self.frames {
+            Frames::Still(glyphs) => {
+                let mut glyphs = *glyphs;
+                if matches!(self.beat_ms, Some(b) if ms - b < BEAT_MS) {
+                    glyphs[DIGITS - 1] |= BEAT_GLYPH;
+                }
+                glyphs
+            }
+            Frames::Marquee(text, step_ms) => marquee_glyphs(text, (t / step_ms) as usize),
+            Frames::Spinner => spinner_glyphs((t / SPINNER_MS) as usize),
+        }
+    }
+}
+
+impl Display for C5412Display {
+    fn show_number(&mut self, n: u32) {
+        self.show(Frames::Still(number_glyphs(n, Instant::now().as_millis())));
+    }
+    fn show_text(&mut self, s: &str) {
+        if s.chars().count() <= DIGITS {
+            self.show(Frames::Still(text_glyphs(s)));
+        } else {
+            let text = s.chars().map(glyph::font).take(TEXT_CHARS).collect();
+            self.show(Frames::Marquee(text, self.scroll_ms));
+        }
+    }
+    fn show_status(&mut self, status: Status) {
+        match status {
+            Status::NoSignal => self.show_text(NO_SIGNAL_TEXT),
+            _ => self.show_text(&status.short()),
+        }
+    }
+    fn show_spinner(&mut self, _n: usize) {
+        self.show(Frames::Spinner);
+    }
+    fn beat(&mut self) {
+        BEAT_ATOMIC.fetch_add(1, Ordering::Relaxed);
+    }
+    fn lamp_test(&mut self) {
+        LAMP_TEST_ATOMIC.fetch_add(1, Ordering::Relaxed);
+    }
+    fn set_brightness(&mut self, level: u8) {
+        BRIGHTNESS_ATOMIC.store((level as usize * LEVELS / 256) as u32, Ordering::Relaxed);
+    }
+    fn refresh_stats(&self) -> (u32, u32) {
+        (get_count(), get_overrun())
+    }
+}
+
+// A GPIO as its port (0 for GPIOA) and pin number
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PinId {
+    pub port: u8,
+    pub pin: u8,
+}
+
+impl PinId {
+    // Add driving this pin high or low to a BSRR word per port
+    fn drive(&self, bsrr: &mut [u32; PORTS], high: bool) {
+        let shift = if high { self.pin } else { self.pin + 16 };
+        bsrr[self.port as usize] |= 1 << shift;
+    }
+}
+
+// Where the display is wired, from a PinMapBuilder
+pub struct PinMap {
+    commons: [[PinId; CATHODE_PINS]; DIGITS],
+    segments: [PinId; glyph::SEGMENTS],
+}
+
+impl PinMap {
+    // Return a bit for each port used, GPIOA being bit 0
+    pub fn ports(&self) -> u32 {
+        self.commons
+            .iter()
+            .flatten()
+            .chain(self.segments.iter())
+            .fold(0, |ports, id| ports | 1 << id.port)
+    }
+    // Return the letters of the ports used, eg. "ABD"
+    pub fn port_names(&self) -> String<PORTS> {
+        let ports = self.ports();
+        (0..PORTS)
+            .filter(|port| ports & (1 << port) != 0)
+            .map(|port| char::from(b'A' + port as u8))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PinMapError {
+    NoSuchDigit(usize),
+    NoSuchSegment(usize),
+    TooManyCathodes(usize), // On this digit
+    Unusable(PinId),        // Not on GPIOA..GPIOG, or no such pin
+    Reused(PinId),          // Assigned twice
+    Missing,                // Some cathode or segment has no pin
+}
+
+// Collects the pin assignments, and checks them all at build()
+// The first bad assignment is the one reported
+pub struct PinMapBuilder {
+    commons: [Vec<PinId, CATHODE_PINS>; DIGITS],
+    segments: [Option<PinId>; glyph::SEGMENTS],
+    error: Option<PinMapError>,
+}
+
+impl PinMapBuilder {
+    pub fn new() -> PinMapBuilder {
+        PinMapBuilder {
+            commons: core::array::from_fn(|_| Vec::new()),
+            segments: [None; glyph::SEGMENTS],
+            error: None,
+        }
+    }
+    fn fail(&mut self, e: PinMapError) {
+        self.error = self.error.or(Some(e));
+    }
+    // Add a cathode pin to digit d, 0 being the leftmost
+    pub fn common(mut self, d: usize, id: PinId) -> PinMapBuilder {
+        match self.commons.get_mut(d) {
+            Some(pins) => {
+                if pins.push(id).is_err() {
+                    self.fail(PinMapError::TooManyCathodes(d));
+                }
+            }
+            None => self.fail(PinMapError::NoSuchDigit(d)),
+        }
+        self
+    }
+    // Set the pin for segment seg, 0 being a
+    pub fn segment(mut self, seg: usize, id: PinId) -> PinMapBuilder {
+        match self.segments.get_mut(seg) {
+            Some(pin) if pin.is_none() => *pin = Some(id),
+            Some(_) => self.fail(PinMapError::Reused(id)),
+            None => self.fail(PinMapError::NoSuchSegment(seg)),
+        }
+        self
+    }
+    pub fn build(self) -> Result<PinMap, PinMapError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.commons.iter().any(|pins| !pins.is_full()) || self.segments.iter().any(|pin| pin.is_none()) {
+            return Err(PinMapError::Missing);
+        }
+        let map = PinMap {
+            commons: core::array::from_fn(|d| core::array::from_fn(|i| self.commons[d][i])),
+            segments: core::array::from_fn(|seg| self.segments[seg].unwrap_or(PinId { port: 0, pin: 0 })),
+        };
+        let mut used = [0u16; PORTS];
+        for id in map.commons.iter().flatten().chain(map.segments.iter()) {
+            if id.port as usize >= PORTS || id.pin >= 16 {
+                return Err(PinMapError::Unusable(*id));
+            }
+            if used[id.port as usize] & (1 << id.pin) != 0 {
+                return Err(PinMapError::Reused(*id));
+            }
+            used[id.port as usize] |= 1 << id.pin;
+        }
+        Ok(map)
+    }
+}
 
+// The GPIOs, held so they stay configured as outputs, and where they are
 pub struct C5412Pins {
-    pub p11: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p12: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p13: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p14: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p15: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p16: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p17: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p18: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p21: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p22: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p23: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p24: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p25: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p26: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p27: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub p28: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub sah: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub sbh: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub sch: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub sdh: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub seh: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub sfh: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub sjh: embassy_stm32::gpio::Output<'static, AnyPin>,
-    pub snh: embassy_stm32::gpio::Output<'static, AnyPin>,
+    _outputs: Vec<Output<'static, AnyPin>, { CATHODE_PINS * DIGITS + glyph::SEGMENTS }>,
+    map: PinMap,
 }
 
 impl C5412Pins {
-    pub fn common_off(&mut self) {
-        self.p11.set_level(High);
-        self.p12.set_level(High);
-        self.p13.set_level(High);
-        self.p14.set_level(High);
-        self.p15.set_level(High);
-        self.p16.set_level(High);
-        self.p17.set_level(High);
-        self.p18.set_level(High);
-        self.p21.set_level(High);
-        self.p22.set_level(High);
-        self.p23.set_level(High);
-        self.p24.set_level(High);
-        self.p25.set_level(High);
-        self.p26.set_level(High);
-        self.p27.set_level(High);
-        self.p28.set_level(High);
-    }
-    pub fn a_n_off(&mut self) {
-        self.sah.set_level(Low);
-        self.sbh.set_level(Low);
-        self.sch.set_level(Low);
-        self.sdh.set_level(Low);
-        self.seh.set_level(Low);
-        self.sfh.set_level(Low);
-        self.sjh.set_level(Low);
-        self.snh.set_level(Low);
-    }
-
-    pub fn all_off(&mut self) {
-        self.common_off();
-        self.a_n_off();
-    }
-
-    pub fn common_1_on(&mut self) {
-        self.p11.set_level(Low);
-        self.p12.set_level(Low);
-        self.p13.set_level(Low);
-        self.p14.set_level(Low);
-        self.p15.set_level(Low);
-        self.p16.set_level(Low);
-        self.p17.set_level(Low);
-        self.p18.set_level(Low);
-    }
-
-    pub fn common_2_on(&mut self) {
-        self.p21.set_level(Low);
-        self.p22.set_level(Low);
-        self.p23.set_level(Low);
-        self.p24.set_level(Low);
-        self.p25.set_level(Low);
-        self.p26.set_level(Low);
-        self.p27.set_level(Low);
-        self.p28.set_level(Low);
-    }
-
-#[rustfmt::skip]
-    pub fn digit_on(&mut self, digit: u8) {
-        match digit {
-            0 => { self.sah.set_level(High); self.sbh.set_level(High); self.sch.set_level(High); self.sdh.set_level(High);
-                   self.seh.set_level(High); self.sfh.set_level(High);                                                     }
-            1 => {                           self.sbh.set_level(High); self.sch.set_level(High);
-                                                                                                                           }
-            2 => { self.sah.set_level(High); self.sbh.set_level(High);                           self.sdh.set_level(High);
-                   self.seh.set_level(High);                           self.sjh.set_level(High); self.snh.set_level(High); }
-            3 => { self.sah.set_level(High); self.sbh.set_level(High); self.sch.set_level(High); self.sdh.set_level(High);
-                   self.sjh.set_level(High); self.snh.set_level(High); }
-            4 => {                           self.sbh.set_level(High); self.sch.set_level(High);
-                                             self.sfh.set_level(High); self.sjh.set_level(High); self.snh.set_level(High); }
-            5 => { self.sah.set_level(High);                           self.sch.set_level(High); self.sdh.set_level(High);
-                   self.sfh.set_level(High); self.sjh.set_level(High); self.snh.set_level(High); }
-            6 => { self.sah.set_level(High);                           self.sch.set_level(High); self.sdh.set_level(High);
-                   self.seh.set_level(High); self.sfh.set_level(High); self.sjh.set_level(High); self.snh.set_level(High); }
-            7 => { self.sah.set_level(High); self.sbh.set_level(High); self.sch.set_level(High);
-                                                                                                                           }
-            8 => { self.sah.set_level(High); self.sbh.set_level(High); self.sch.set_level(High); self.sdh.set_level(High);
-                   self.seh.set_level(High); self.sfh.set_level(High); self.sjh.set_level(High); self.snh.set_level(High); }
-            9 => { self.sah.set_level(High); self.sbh.set_level(High); self.sch.set_level(High); self.sdh.set_level(High);
-                   self.sfh.set_level(High); self.sjh.set_level(High); self.snh.set_level(High); }
-            _ => {}
+    // commons: cathodes of each digit, left to right
+    // segments: a..n
+    // All start off: cathodes high, segments low
+    pub fn new(
+        commons: [[AnyPin; CATHODE_PINS]; DIGITS],
+        segments: [AnyPin; glyph::SEGMENTS],
+    ) -> Result<C5412Pins, PinMapError> {
+        let id = |pin: &AnyPin| PinId {
+            port: pin.port(),
+            pin: pin.pin(),
+        };
+        let mut builder = PinMapBuilder::new();
+        for (d, pins) in commons.iter().enumerate() {
+            for pin in pins.iter() {
+                builder = builder.common(d, id(pin));
+            }
+        }
+        for (seg, pin) in segments.iter().enumerate() {
+            builder = builder.segment(seg, id(pin));
+        }
+        let map = builder.build()?;
+        let mut outputs = Vec::new();
+        for pin in commons.into_iter().flatten() {
+            _ = outputs.push(Output::new(pin, Level::High, Speed::Low));
         }
+        for pin in segments {
+            _ = outputs.push(Output::new(pin, Level::Low, Speed::Low));
+        }
+        Ok(C5412Pins { _outputs: outputs, map })
+    }
+    pub fn map(&self) -> &PinMap {
+        &self.map
     }
 }
 
-#[embassy_executor::task]
-pub async fn process(c5412pins_ref: &'static mut C5412Pins, value_atomic: &'static AtomicU32) // What value to display
-{
-    const ON_TIME_MS: u64 = 2;
-    const OFF_TIME_MS: u64 = 5;
-    let mut count: u32 = 0; // For profiling the refresh rate. Just counts
-    let mut when = Instant::now().as_millis();
-    let mut overrun: u32 = 0;
-    loop {
-        COUNT_ATOMIC.store(count, Ordering::Relaxed);
-        OVERRUN_ATOMIC.store(overrun, Ordering::Relaxed);
-        let x: u32 = value_atomic.load(Ordering::Relaxed); // What to display
+// Somewhere to write BSRR words: the GPIO ports, or a mock in tests
+trait Bsrr {
+    fn write(&mut self, port: usize, word: u32);
+}
+
+struct Gpios;
+
+impl Bsrr for Gpios {
+    fn write(&mut self, port: usize, word: u32) {
+        GPIOS[port].bsrr().write_value(pac::gpio::regs::Bsrr(word));
+    }
+}
 
-        // Cathode 1: The 10's digit
-        c5412pins_ref.common_1_on();
-        c5412pins_ref.digit_on(((x / 10) % 10) as u8);
-        when += ON_TIME_MS;
-        Timer::at(Instant::from_millis(when)).await;
-        if Instant::now().as_millis() > when {
-            overrun += 1;
+// The steps of a phase, in the order they're written
+const SEGMENTS_OFF: usize = 0;
+const COMMONS: usize = 1;
+const SEGMENTS_ON: usize = 2;
+const STEPS: usize = 3;
+
+// One step of the multiplexing: what to write to each port at each step, and
+//   how long until the next phase
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Phase {
+    bsrr: [[u32; PORTS]; STEPS],
+    us: u32,
+}
+
+const PHASE_OFF: Phase = Phase {
+    bsrr: [[0; PORTS]; STEPS],
+    us: PERIOD_US as u32,
+};
+
+// Renders a glyph into a phase's BSRR words
+struct PhaseSegments<'a> {
+    map: &'a PinMap,
+    bsrr: &'a mut [[u32; PORTS]; STEPS],
+}
+
+impl Segments for PhaseSegments<'_> {
+    fn set(&mut self, seg: usize, on: bool) {
+        let step = if on { SEGMENTS_ON } else { SEGMENTS_OFF };
+        self.map.segments[seg].drive(&mut self.bsrr[step], on);
+    }
+}
+
+// Switch to phase, step by step, one write per port used in each, so all the
+//   cathodes on a port switch together, and only while the segments are off
+fn apply(phase: &Phase, regs: &mut impl Bsrr) {
+    for words in phase.bsrr.iter() {
+        for (port, word) in words.iter().enumerate() {
+            if *word != 0 {
+                regs.write(port, *word);
+            }
         }
-        c5412pins_ref.all_off();
-        when += OFF_TIME_MS;
-        Timer::at(Instant::from_millis(when)).await;
-        if Instant::now().as_millis() > when {
-            overrun += 1;
+    }
+}
+
+// Return the phases to show glyphs at brightness level: each digit on for its
+//   on time, then everything off for the rest of its period
+fn schedule(map: &PinMap, glyphs: &[Glyph; DIGITS], level: usize) -> [Phase; PHASES] {
+    let mut phases = [PHASE_OFF; PHASES];
+    let mut off = [[0u32; PORTS]; STEPS];
+    glyph::render(glyph::BLANK, &mut PhaseSegments { map, bsrr: &mut off });
+    for commons in map.commons.iter() {
+        for id in commons.iter() {
+            id.drive(&mut off[COMMONS], true);
+        }
+    }
+    for (d, g) in glyphs.iter().enumerate() {
+        let mut on = [[0u32; PORTS]; STEPS];
+        glyph::render(*g, &mut PhaseSegments { map, bsrr: &mut on });
+        for (c, commons) in map.commons.iter().enumerate() {
+            for id in commons.iter() {
+                id.drive(&mut on[COMMONS], c != d);
+            }
         }
+        let on_us = on_time_us(level, *g).clamp(MIN_PHASE_US, PERIOD_US - MIN_PHASE_US);
+        phases[2 * d] = Phase {
+            bsrr: on,
+            us: on_us as u32,
+        };
+        phases[2 * d + 1] = Phase {
+            bsrr: off,
+            us: (PERIOD_US - on_us) as u32,
+        };
+    }
+    phases
+}
 
-        // Cathode 2: The 1's digit
-        c5412pins_ref.common_2_on();
-        c5412pins_ref.digit_on((x % 10) as u8);
-        when += ON_TIME_MS;
-        Timer::at(Instant::from_millis(when)).await;
-        if Instant::now().as_millis() > when {
-            overrun += 1;
+// Shared between process() and the timer interrupt
+struct Mux {
+    phases: [Phase; PHASES],       // Refresh being shown
+    next: Option<[Phase; PHASES]>, // Refresh to show after that
+    ix: usize,                     // Phase to show at the next interrupt
+}
+
+static MUX: Mutex<CriticalSectionRawMutex, RefCell<Mux>> = Mutex::new(RefCell::new(Mux {
+    phases: [PHASE_OFF; PHASES],
+    next: None,
+    ix: 0,
+}));
+
+// Show the next phase, and time the one after
+#[interrupt]
+unsafe fn TIM7() {
+    let tim = pac::TIM7;
+    let mut late = tim.cnt().read().cnt() as u32 > LATE_US;
+    tim.sr().modify(|r| r.set_uif(false));
+    MUX.lock(|mux| {
+        let mut mux = mux.borrow_mut();
+        if mux.ix == 0 {
+            if let Some(phases) = mux.next.take() {
+                mux.phases = phases;
+            }
+            COUNT_ATOMIC.fetch_add(1, Ordering::Relaxed);
         }
-        c5412pins_ref.all_off();
-        when += OFF_TIME_MS;
-        Timer::at(Instant::from_millis(when)).await;
-        if Instant::now().as_millis() > when {
-            overrun += 1;
+        let phase = mux.phases[mux.ix];
+        apply(&phase, &mut Gpios);
+        // No preload, so this is the length of the period that just started
+        // If the counter is already past it, it would run on to 0xffff and
+        //   wrap, holding this phase for 65ms, so end it right away instead
+        tim.arr().write(|r| r.set_arr((phase.us - 1) as u16));
+        if tim.cnt().read().cnt() as u32 + 1 >= phase.us {
+            tim.egr().write(|r| r.set_ug(true));
+            late = true;
         }
+        mux.ix = (mux.ix + 1) % PHASES;
+    });
+    if late {
+        OVERRUN_ATOMIC.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
-        count += 1;
+// Start TIM7 counting microseconds, interrupting at the end of each phase
+fn start_timer(_tim: peripherals::TIM7) {
+    pac::RCC.apb1lenr().modify(|w| w.set_tim7en(true));
+    let tim = pac::TIM7;
+    tim.psc().write_value((TIMER_HZ / 1_000_000 - 1) as u16);
+    tim.arr().write(|r| r.set_arr((PERIOD_US - 1) as u16));
+    tim.egr().write(|r| r.set_ug(true));
+    tim.sr().modify(|r| r.set_uif(false));
+    tim.dier().modify(|r| r.set_uie(true));
+    interrupt::TIM7.set_priority(Priority::P1);
+    interrupt::TIM7.unpend();
+    unsafe { interrupt::TIM7.enable() };
+    tim.cr1().modify(|r| r.set_cen(true));
+}
+
+#[embassy_executor::task]
+pub async fn process(
+    c5412pins_ref: &'static C5412Pins,
+    tim: peripherals::TIM7,
+    frames: &'static FrameSignal, // What to display
+) {
+    let mut sequencer = Sequencer::new();
+    let mut beats = BEAT_ATOMIC.load(Ordering::Relaxed);
+    let mut lamp_tests = LAMP_TEST_ATOMIC.load(Ordering::Relaxed);
+    start_timer(tim);
+    let mut when = Instant::now().as_micros();
+    sequencer.lamp_test(when / 1000, true);
+    loop {
+        // Work out the next refresh's schedule while the timer shows this one
+        let ms = when / 1000;
+        if let Some(f) = frames.try_take() {
+            sequencer.set(f, ms);
+        }
+        let b = BEAT_ATOMIC.load(Ordering::Relaxed);
+        if b != beats {
+            beats = b;
+            sequencer.beat(ms);
+        }
+        let l = LAMP_TEST_ATOMIC.load(Ordering::Relaxed);
+        if l != lamp_tests {
+            lamp_tests = l;
+            sequencer.lamp_test(ms, false);
+        }
+        let level = BRIGHTNESS_ATOMIC.load(Ordering::Relaxed) as usize;
+        let phases = schedule(c5412pins_ref.map(), &sequencer.glyphs(ms), level);
+        MUX.lock(|mux| mux.borrow_mut().next = Some(phases));
+        when += FRAME_US;
+        Timer::at(Instant::from_micros(when)).await;
     }
 }
 
@@ -190,3 +713,323 @@ pub fn get_count() -> u32 {
 pub fn get_overrun() -> u32 {
     OVERRUN_ATOMIC.load(Ordering::Relaxed)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow() {
+        assert_eq!(number_glyphs(72, 0), text_glyphs("72"));
+        assert_eq!(number_glyphs(7, 0), text_glyphs(" 7"));
+        // 120 alternates between "1 " and "20"
+        assert_eq!(number_glyphs(120, 0), text_glyphs("1"));
+        assert_eq!(number_glyphs(120, OVERFLOW_MS), text_glyphs("20"));
+        assert_eq!(number_glyphs(105, OVERFLOW_MS + 1), text_glyphs("05"));
+    }
+
+    #[test]
+    fn marquee() {
+        let text: Vec<Glyph, TEXT_CHARS> = "HI THERE".chars().map(glyph::font).collect();
+        let mut sequencer = Sequencer::new();
+        sequencer.set(Frames::Marquee(text.clone(), 300), 1000);
+        assert_eq!(sequencer.glyphs(1000), text_glyphs(""));
+        assert_eq!(sequencer.glyphs(1300), text_glyphs(" H"));
+        assert_eq!(sequencer.glyphs(1600), text_glyphs("HI"));
+        assert_eq!(sequencer.glyphs(1000 + 300 * 9), text_glyphs("E"));
+        // And round again
+        assert_eq!(sequencer.glyphs(1000 + 300 * 10), text_glyphs(""));
+        // Interrupted, and back again, it carries on rather than restarting
+        sequencer.set(Frames::Still(text_glyphs("AL")), 4200);
+        sequencer.set(Frames::Marquee(text.clone(), 300), 4500);
+        assert_eq!(sequencer.glyphs(4600), text_glyphs("HI"));
+        // But other text starts from the beginning
+        let other: Vec<Glyph, TEXT_CHARS> = "OTHER".chars().map(glyph::font).collect();
+        sequencer.set(Frames::Marquee(other, 300), 5000);
+        assert_eq!(sequencer.glyphs(5000), text_glyphs(""));
+    }
+
+    #[test]
+    fn spinner() {
+        // Each outside segment once, then repeat
+        let mut lit = [glyph::BLANK; DIGITS];
+        for step in 0..2 * DIGITS + 4 {
+            let glyphs = spinner_glyphs(step);
+            assert_eq!(glyphs.iter().map(|g| g.count_ones()).sum::<u32>(), 1);
+            for (l, g) in lit.iter_mut().zip(glyphs.iter()) {
+                assert_eq!(*l & *g, 0);
+                *l |= *g;
+            }
+        }
+        assert_eq!(lit[0], glyph::A | glyph::D | glyph::E | glyph::F);
+        assert_eq!(lit[DIGITS - 1], glyph::A | glyph::B | glyph::C | glyph::D);
+        assert_eq!(spinner_glyphs(2 * DIGITS + 4), spinner_glyphs(0));
+    }
+
+    #[test]
+    fn brightness() {
+        // Brighter each level, but never on all the time
+        let all = (1 << glyph::SEGMENTS) - 1;
+        for level in 1..LEVELS {
+            assert!(on_time_us(level, all) > on_time_us(level - 1, all));
+        }
+        // Never on longer than the display was first driven at, not even "8"
+        assert!(on_time_us(LEVELS - 1, all) <= PERIOD_US * 2 / 7);
+        assert!(on_time_us(LEVELS - 1, glyph::font('8')) <= PERIOD_US * 2 / 7);
+        // Fewer lit segments get a bigger share of the current, so less time
+        let one = glyph::font('1');
+        let eight = glyph::font('8');
+        assert!(on_time_us(5, one) < on_time_us(5, eight));
+        assert!(on_time_us(5, eight) < on_time_us(5, all));
+    }
+
+    // Cathodes on port, digit 0 on pins 0..7 and 1 on 8..15
+    fn commons(port: u8) -> PinMapBuilder {
+        let mut builder = PinMapBuilder::new();
+        for d in 0..DIGITS {
+            for i in 0..CATHODE_PINS {
+                let pin = (d * CATHODE_PINS + i) as u8;
+                builder = builder.common(d, PinId { port, pin });
+            }
+        }
+        builder
+    }
+
+    // And segments a..n on pins 0..13 of port
+    fn segments(mut builder: PinMapBuilder, port: u8) -> PinMapBuilder {
+        for seg in 0..glyph::SEGMENTS {
+            builder = builder.segment(seg, PinId { port, pin: seg as u8 });
+        }
+        builder
+    }
+
+    // Cathodes on GPIOA, segments on GPIOB
+    fn map() -> PinMap {
+        segments(commons(0), 1).build().unwrap()
+    }
+
+    // The board's wiring: cathodes of each digit, then segments a..n
+    fn board() -> PinMap {
+        let id = |port: u8, pin: u8| PinId { port, pin };
+        let (a, b, c, d, e, f, g) = (0, 1, 2, 3, 4, 5, 6);
+        let commons = [
+            [
+                id(d, 7),
+                id(d, 6),
+                id(d, 5),
+                id(d, 4),
+                id(d, 3),
+                id(e, 2),
+                id(f, 2),
+                id(f, 1),
+            ],
+            [
+                id(e, 4),
+                id(e, 5),
+                id(e, 6),
+                id(e, 3),
+                id(f, 8),
+                id(f, 7),
+                id(f, 9),
+                id(g, 1),
+            ],
+        ];
+        let segments = [
+            id(c, 0),
+            id(b, 1),
+            id(d, 1),
+            id(d, 0),
+            id(g, 0),
+            id(f, 10),
+            id(e, 7),
+            id(e, 8),
+            id(e, 10),
+            id(f, 0),
+            id(e, 12),
+            id(e, 14),
+            id(e, 15),
+            id(a, 3),
+        ];
+        let mut builder = PinMapBuilder::new();
+        for (digit, pins) in commons.iter().enumerate() {
+            for pin in pins.iter() {
+                builder = builder.common(digit, *pin);
+            }
+        }
+        for (seg, pin) in segments.iter().enumerate() {
+            builder = builder.segment(seg, *pin);
+        }
+        builder.build().unwrap()
+    }
+
+    // Output levels of each port, as set through BSRR
+    // After each write, checks that no digit with a segment lit has some of its
+    //   cathodes on and some off
+    struct MockGpios<'a> {
+        map: &'a PinMap,
+        odr: [u16; PORTS],
+        writes: u32,
+    }
+
+    impl Bsrr for MockGpios<'_> {
+        fn write(&mut self, port: usize, word: u32) {
+            self.odr[port] = (self.odr[port] & !(word >> 16) as u16) | word as u16;
+            self.writes += 1;
+            let high = |id: &PinId| self.odr[id.port as usize] & (1 << id.pin) != 0;
+            if self.map.segments.iter().any(high) {
+                for commons in self.map.commons.iter() {
+                    let on = commons.iter().filter(|id| !high(id)).count();
+                    assert!(on == 0 || on == CATHODE_PINS);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pin_map() {
+        let map = map();
+        assert_eq!(map.ports(), 0b11);
+        assert_eq!(map.port_names(), "AB");
+        let a0 = PinId { port: 0, pin: 0 };
+        let b0 = PinId { port: 1, pin: 0 };
+        let h0 = PinId { port: 7, pin: 0 };
+        let err = |b: PinMapBuilder| b.build().err();
+        assert_eq!(err(commons(0)), Some(PinMapError::Missing));
+        assert_eq!(
+            err(segments(commons(0), 1).common(DIGITS, b0)),
+            Some(PinMapError::NoSuchDigit(DIGITS))
+        );
+        assert_eq!(
+            err(segments(commons(0), 1).common(0, b0)),
+            Some(PinMapError::TooManyCathodes(0))
+        );
+        assert_eq!(
+            err(segments(commons(0), 1).segment(14, b0)),
+            Some(PinMapError::NoSuchSegment(14))
+        );
+        assert_eq!(
+            err(segments(commons(0), 1).segment(0, b0)),
+            Some(PinMapError::Reused(b0))
+        );
+        // The same pin as a cathode and a segment
+        assert_eq!(err(segments(commons(0), 0)), Some(PinMapError::Reused(a0)));
+        assert_eq!(err(segments(commons(7), 1)), Some(PinMapError::Unusable(h0)));
+    }
+
+    #[test]
+    fn switching() {
+        let map = map();
+        let mut gpios = MockGpios {
+            map: &map,
+            odr: [0; PORTS],
+            writes: 0,
+        };
+        let phases = schedule(&map, &text_glyphs("8"), 5);
+        // Digit 0 on: all its cathodes low at once, the other digit's high,
+        //   between turning the unlit segments off and the lit ones on
+        apply(&phases[0], &mut gpios);
+        assert_eq!(gpios.writes, 3);
+        assert_eq!(gpios.odr[0], 0xff00);
+        assert_eq!(gpios.odr[1], glyph::font('8'));
+        // Then all off, segments first
+        apply(&phases[1], &mut gpios);
+        assert_eq!(gpios.writes, 5);
+        assert_eq!(gpios.odr[0], 0xffff);
+        assert_eq!(gpios.odr[1], 0);
+        assert!(gpios.odr[2..].iter().all(|odr| *odr == 0));
+    }
+
+    #[test]
+    fn switching_across_ports() {
+        // On the board, cathodes span three ports, so a digit's cathodes are
+        //   switched over several writes; no segment may be lit meanwhile
+        let map = board();
+        assert_eq!(map.port_names(), "ABCDEFG");
+        let mut gpios = MockGpios {
+            map: &map,
+            odr: [0; PORTS],
+            writes: 0,
+        };
+        apply(&schedule(&map, &text_glyphs(""), 0)[1], &mut gpios);
+        for text in ["88", "WX", "1", " 7", "88"] {
+            for phase in schedule(&map, &text_glyphs(text), LEVELS - 1).iter() {
+                apply(phase, &mut gpios);
+            }
+        }
+        // All off at the end
+        assert!(map
+            .segments
+            .iter()
+            .all(|id| gpios.odr[id.port as usize] & (1 << id.pin) == 0));
+        assert!(map
+            .commons
+            .iter()
+            .flatten()
+            .all(|id| gpios.odr[id.port as usize] & (1 << id.pin) != 0));
+    }
+
+    #[test]
+    fn phases() {
+        let one = glyph::font('1') as u32;
+        let segments = (1 << glyph::SEGMENTS) - 1;
+        let phases = schedule(&map(), &text_glyphs("1"), 5);
+        // Digit 0 on: the other segments off, its cathodes low and the others
+        //   high, then the segments of "1" on
+        assert_eq!(phases[0].bsrr[SEGMENTS_OFF][1], (segments & !one) << 16);
+        assert_eq!(phases[0].bsrr[COMMONS][0], 0xff << 16 | 0xff00);
+        assert_eq!(phases[0].bsrr[SEGMENTS_ON][1], one);
+        assert!(phases[0].bsrr.iter().all(|words| words[2] == 0));
+        // Then everything off
+        assert_eq!(phases[1].bsrr[SEGMENTS_OFF][1], segments << 16);
+        assert_eq!(phases[1].bsrr[COMMONS][0], 0xffff);
+        assert_eq!(phases[1].bsrr[SEGMENTS_ON], [0; PORTS]);
+        // Digit 1 on, but blank
+        assert_eq!(phases[2].bsrr[SEGMENTS_OFF][1], segments << 16);
+        assert_eq!(phases[2].bsrr[COMMONS][0], 0xff00 << 16 | 0xff);
+        assert_eq!(phases[2].bsrr[SEGMENTS_ON], [0; PORTS]);
+        assert!(phases[2].us < phases[0].us);
+        // Refresh rate doesn't change
+        assert_eq!(phases.iter().map(|p| p.us as u64).sum::<u64>(), FRAME_US);
+    }
+
+    #[test]
+    fn lamp_test() {
+        let mut sequencer = Sequencer::new();
+        sequencer.set(Frames::Still(text_glyphs("72")), 0);
+        sequencer.lamp_test(1000, true);
+        // Every segment of every digit, one at a time
+        let mut lit = [glyph::BLANK; DIGITS];
+        for step in 0..LAMP_STEPS {
+            let glyphs = sequencer.glyphs(1000 + step * LAMP_MS);
+            assert_eq!(glyphs.iter().map(|g| g.count_ones()).sum::<u32>(), 1);
+            for (l, g) in lit.iter_mut().zip(glyphs.iter()) {
+                *l |= *g;
+            }
+        }
+        assert!(lit.iter().all(|l| *l == (1 << glyph::SEGMENTS) - 1));
+        // Then the version, with 'V' coming in first
+        let version_ms = 1000 + LAMP_STEPS * LAMP_MS;
+        assert_eq!(sequencer.glyphs(version_ms), text_glyphs(""));
+        assert_eq!(
+            sequencer.glyphs(version_ms + VERSION_SCROLL_MS)[DIGITS - 1],
+            glyph::font('V')
+        );
+        // Then ready, then back to what it was told to show
+        let ready_ms = version_ms + (VERSION.len() + DIGITS) as u64 * VERSION_SCROLL_MS;
+        assert_eq!(sequencer.glyphs(ready_ms), ready_glyphs());
+        assert_eq!(sequencer.glyphs(ready_ms + READY_MS), text_glyphs("72"));
+        // Rerun on its own
+        sequencer.lamp_test(20000, false);
+        assert_eq!(sequencer.glyphs(20000)[0], glyph::A);
+        assert_eq!(sequencer.glyphs(20000 + LAMP_STEPS * LAMP_MS), text_glyphs("72"));
+    }
+
+    #[test]
+    fn beat() {
+        let mut sequencer = Sequencer::new();
+        sequencer.set(Frames::Still(text_glyphs("72")), 0);
+        sequencer.beat(500);
+        assert_eq!(sequencer.glyphs(500)[DIGITS - 1], glyph::font('2') | BEAT_GLYPH);
+        assert_eq!(sequencer.glyphs(500 + BEAT_MS), text_glyphs("72"));
+    }
+}
@@ -0,0 +1,71 @@
+// console: Single key commands typed on the UART console
+//
+// The HR task owns the UART transmitter, so the receiver runs in its own task
+// and passes commands over a channel.  The HR task picks them up between
+// samples and does whatever output they call for.  Each key is acknowledged
+// on the display, through the display signal, so it's clear it got through.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+
+use crate::display::{format, Content, DisplayContent, DisplaySignal, Priority};
+
+const ACK_MS: usize = 1000; // Show the acknowledgement this long
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Template, // 't': Dump the ensemble averaged pulse template
+    Brighter, // '+': Turn the display brightness up a step
+    Dimmer,   // '-': Turn the display brightness down a step
+    LampTest, // 'l': Light each display segment in turn, to check the wiring
+}
+
+impl Command {
+    pub fn from_key(key: u8) -> Option<Command> {
+        match key {
+            b't' => Some(Command::Template),
+            b'+' => Some(Command::Brighter),
+            b'-' => Some(Command::Dimmer),
+            b'l' => Some(Command::LampTest),
+            _ => None,
+        }
+    }
+    // What to show on the display when the command is typed, if anything
+    //   The lamp test shows itself
+    fn ack(&self) -> Option<&'static str> {
+        match self {
+            Command::Template => Some("TPL"),
+            Command::Brighter => Some("BRT"),
+            Command::Dimmer => Some("DIM"),
+            Command::LampTest => None,
+        }
+    }
+}
+
+pub type CommandChannel = Channel<CriticalSectionRawMutex, Command, 4>;
+
+#[embassy_executor::task]
+pub async fn process(
+    rx_ref: &'static mut crate::UARTRX,
+    command_channel: &'static CommandChannel,
+    display_signal: &'static DisplaySignal,
+) {
+    let mut key = [0u8; 1];
+    loop {
+        if rx_ref.read(&mut key).await.is_ok() {
+            let ack = match Command::from_key(key[0]) {
+                Some(command) => {
+                    // If the HR task is that far behind, drop it; the user can type again
+                    _ = command_channel.try_send(command);
+                    command.ack()
+                }
+                None => Some("?"),
+            };
+            if let Some(text) = ack {
+                let m =
+                    DisplayContent::message(Content::Text(format(format_args!("{}", text))), Priority::Info, ACK_MS);
+                display_signal.signal(m);
+            }
+        }
+    }
+}
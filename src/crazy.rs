@@ -0,0 +1,165 @@
+// crazy: Adaptive "crazy" window for motion artifact rejection
+//
+// Samples further than lo below or hi above the DC estimate are considered
+// crazy (sensor motion) and reset the HR state machine.  Rather than fixed
+// offsets, the window is a multiple of the median absolute deviation (MAD) of
+// the baseline-removed signal over the last few seconds, so it follows the
+// pulse amplitude of whoever is holding the sensor.
+//
+// * Asymmetric: pulses stick up from the baseline much more than they dip
+//   below it, so the high side gets a larger multiplier
+// * Floor/ceiling: the window can never shrink onto the noise or grow to
+//   accept anything
+// * Freezing: crazy samples are not fed into the statistics, or the window
+//   would grow to accept the very artifacts it is meant to reject.  But if
+//   everything is crazy for a long time, the window is probably too tight for
+//   a new, stronger signal, so adaptation is forced back on rather than
+//   staying stuck forever.
+
+use libm::fabs;
+use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
+
+const DECIMATE: usize = 16; // Keep every this many samples
+const HISTORY: usize = 256; // Decimated samples kept, 4.1s at 1kHz
+const RECALC: usize = 250; // Samples between recomputing the window
+const MIN_HISTORY: usize = 64; // Decimated samples needed before adapting
+
+const LO_MULT: f64 = 7.0;
+const HI_MULT: f64 = 20.0;
+const LO_FLOOR: f64 = 400.0;
+const HI_FLOOR: f64 = 1000.0;
+const LO_CEIL: f64 = 4000.0;
+const HI_CEIL: f64 = 12000.0;
+
+const LO_DEFAULT: u32 = 1000; // Used until enough history is collected
+const HI_DEFAULT: u32 = 3000;
+
+const HOLDOFF: usize = 500; // Samples to stay frozen after the last crazy one
+const MAX_FREEZE: usize = 5000; // Samples frozen before adaptation is forced on
+
+pub struct CrazyWindow {
+    history: ConstGenericRingBuffer<f64, HISTORY>,
+    lo: u32,
+    hi: u32,
+    mad: f64,
+    n: usize,
+    since_crazy: usize, // Samples since the last crazy one
+    frozen_for: usize,  // Samples spent frozen
+}
+
+impl CrazyWindow {
+    pub fn new() -> CrazyWindow {
+        CrazyWindow {
+            history: ConstGenericRingBuffer::<f64, HISTORY>::new(),
+            lo: LO_DEFAULT,
+            hi: HI_DEFAULT,
+            mad: 0.0,
+            n: 0,
+            since_crazy: HOLDOFF,
+            frozen_for: 0,
+        }
+    }
+    // Return window (lo, hi) offsets below and above the DC estimate
+    pub fn window(&self) -> (u32, u32) {
+        (self.lo, self.hi)
+    }
+    // Return the current MAD estimate of the baseline-removed signal
+    pub fn mad(&self) -> f64 {
+        self.mad
+    }
+    // True if adaptation is currently suspended because of crazy samples
+    pub fn frozen(&self) -> bool {
+        self.since_crazy < HOLDOFF
+    }
+    // Update with one sample
+    // Parameters:
+    //    dev: sample minus DC estimate
+    //    crazy: true if the sample fell outside the current window
+    pub fn update(&mut self, dev: f64, crazy: bool) {
+        self.n += 1;
+        if crazy {
+            self.since_crazy = 0;
+        } else {
+            self.since_crazy += 1;
+        }
+        if self.frozen() {
+            self.frozen_for += 1;
+            if self.frozen_for < MAX_FREEZE {
+                return;
+            }
+            // Stuck: adapt to whatever we are seeing, crazy or not
+        } else {
+            self.frozen_for = 0;
+        }
+        if self.n % DECIMATE == 0 {
+            self.history.push(dev);
+        }
+        if self.n % RECALC == 0 && self.history.len() >= MIN_HISTORY {
+            self.recalc();
+        }
+    }
+    fn recalc(&mut self) {
+        let mut buf = [0f64; HISTORY];
+        let len = self.history.len();
+        for (b, h) in buf.iter_mut().zip(self.history.iter()) {
+            *b = *h;
+        }
+        let med = median(&mut buf[..len]);
+        for b in buf[..len].iter_mut() {
+            *b = fabs(*b - med);
+        }
+        self.mad = median(&mut buf[..len]);
+        self.lo = (LO_MULT * self.mad).clamp(LO_FLOOR, LO_CEIL) as u32;
+        self.hi = (HI_MULT * self.mad).clamp(HI_FLOOR, HI_CEIL) as u32;
+    }
+}
+
+// Median of a slice, reordering it in the process
+fn median(buf: &mut [f64]) -> f64 {
+    let mid = buf.len() / 2;
+    let (_, m, _) = buf.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+    *m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Triangle wave of +/-amp with a 1000 sample period
+    fn wave(n: usize, amp: f64) -> f64 {
+        let p = (n % 1000) as f64 / 1000.0;
+        amp * (4.0 * if p < 0.5 { p } else { 1.0 - p } - 1.0)
+    }
+
+    #[test]
+    fn follows_signal() {
+        let mut c = CrazyWindow::new();
+        for n in 0..10000 {
+            c.update(wave(n, 400.0), false);
+        }
+        // MAD of a triangle is half its amplitude
+        assert!((c.mad() - 200.0).abs() < 20.0);
+        let (lo, hi) = c.window();
+        assert!(lo == (LO_MULT * c.mad()) as u32 && hi > lo);
+    }
+
+    #[test]
+    fn freezes_then_unsticks() {
+        let mut c = CrazyWindow::new();
+        for n in 0..10000 {
+            c.update(wave(n, 400.0), false);
+        }
+        let before = c.window();
+        // Short artifact: window doesn't move
+        for n in 0..2000 {
+            c.update(wave(n, 8000.0), true);
+        }
+        assert!(c.frozen());
+        assert_eq!(c.window(), before);
+        // Signal got much stronger and everything looks crazy: adapt anyway
+        for n in 0..10000 {
+            c.update(wave(n, 8000.0), true);
+        }
+        assert!(c.window().1 > before.1);
+    }
+}
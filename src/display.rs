@@ -0,0 +1,336 @@
+// display: What the HR task shows, independent of the display hardware
+//
+// Each display backend implements Display.  The backend is chosen by cargo
+// feature, and is available as display::Active:
+//   display-c5412: 2 character 14-segment LED, multiplexed by c5412::process
+//   display-hd44780: 16x2 character LCD, 4-bit parallel
+//   display-ssd1306: 128x32 I2C OLED
+//   display-mock: In-memory, for host tests
+//
+// Backends are called every sample, so the slow ones only write to the
+// hardware when what is shown changes.
+//
+// What to show is modelled as DisplayContent, and a Screen keeps track of it:
+// a base content, normally the HR reading, and on top of that a temporary
+// message, eg. a new zone or "Lo" for 2 seconds, after which it reverts to the
+// base.  A message only replaces one of the same or lower priority.  Other
+// tasks post messages through a DisplaySignal, which the HR task picks up
+// between samples.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use heapless::String;
+
+use crate::zones::Alarm;
+
+#[cfg(feature = "display-c5412")]
+pub use crate::c5412::C5412Display as Active;
+#[cfg(feature = "display-hd44780")]
+pub use crate::hd44780::Hd44780 as Active;
+#[cfg(feature = "display-ssd1306")]
+pub use crate::ssd1306::Ssd1306 as Active;
+#[cfg(feature = "display-mock")]
+pub use Mock as Active;
+#[cfg(not(any(
+    feature = "display-c5412",
+    feature = "display-hd44780",
+    feature = "display-ssd1306",
+    feature = "display-mock"
+)))]
+compile_error!("Select a display backend with a display-* feature");
+#[cfg(any(
+    all(feature = "display-c5412", feature = "display-hd44780"),
+    all(feature = "display-c5412", feature = "display-ssd1306"),
+    all(feature = "display-c5412", feature = "display-mock"),
+    all(feature = "display-hd44780", feature = "display-ssd1306"),
+    all(feature = "display-hd44780", feature = "display-mock"),
+    all(feature = "display-ssd1306", feature = "display-mock")
+))]
+compile_error!("Select only one display-* feature; the default is display-c5412, so use --no-default-features");
+
+const ALARM_BLINK_MS: usize = 500; // Alarms alternate with the HR this often
+const BLINK_MS: usize = 250; // Blinking content is on this long, then off
+const SPINNER_MS: usize = 100; // Time per spinner frame
+const SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+pub const TEXT_CHARS: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Status {
+    NoSignal,
+    Clipping,
+    Zone(u8), // Just entered this training zone
+    Alarm(Alarm),
+}
+
+impl Status {
+    // Two character version, for the smallest displays
+    pub fn short(&self) -> String<2> {
+        let mut s = String::new();
+        match self {
+            Status::NoSignal => _ = s.push_str("--"),
+            Status::Clipping => _ = s.push_str("Er"),
+            Status::Zone(z) => {
+                _ = s.push('Z');
+                _ = s.push(char::from(b'0' + z));
+            }
+            Status::Alarm(Alarm::High) => _ = s.push_str("Hi"),
+            Status::Alarm(Alarm::Low) => _ = s.push_str("Lo"),
+        }
+        s
+    }
+}
+
+pub trait Display {
+    // Show a heart rate
+    fn show_number(&mut self, n: u32);
+    // Show as much of s as fits
+    fn show_text(&mut self, s: &str);
+    // Show a status, by default as 2 characters
+    fn show_status(&mut self, status: Status) {
+        self.show_text(&status.short());
+    }
+    // Show that something is in progress, at sample number n
+    // By default as a line turning every SPINNER_MS
+    fn show_spinner(&mut self, n: usize) {
+        self.show_text(SPINNER[(n / SPINNER_MS) % SPINNER.len()]);
+    }
+    // Flash a beat indicator, if the backend has one
+    fn beat(&mut self) {}
+    // Light each part of the display in turn to check it works, if the
+    //   backend can
+    fn lamp_test(&mut self) {}
+    // Brightness or contrast, 0 (dimmest) to 255
+    fn set_brightness(&mut self, level: u8);
+    // For profiling: (refreshes, overruns) so far, if the backend keeps them
+    fn refresh_stats(&self) -> (u32, u32) {
+        (0, 0)
+    }
+}
+
+// Format into a string, cutting off whatever doesn't fit
+pub fn format<const N: usize>(args: core::fmt::Arguments) -> String<N> {
+    struct Truncate<const N: usize>(String<N>);
+    impl<const N: usize> core::fmt::Write for Truncate<N> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            for c in s.chars() {
+                _ = self.0.push(c);
+            }
+            Ok(())
+        }
+    }
+    let mut t = Truncate(String::new());
+    _ = core::fmt::write(&mut t, args);
+    t.0
+}
+
+// Not everything is shown by the HR task itself, but any task can post it
+#[allow(dead_code)]
+#[derive(Clone, PartialEq, Debug)]
+pub enum Content {
+    Blank,
+    Number(u32),
+    Text(String<TEXT_CHARS>),
+    Status(Status),
+    Error(u8), // Shown as "E" and the code
+    Spinner,   // Something is in progress
+}
+
+// Order matters: higher priority messages replace lower ones, not vice versa
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Priority {
+    Info,
+    Warning,
+    Alarm,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DisplayContent {
+    pub content: Content,
+    pub blink: bool,
+    pub priority: Priority,
+    pub timeout_ms: usize, // Revert to the base content after this long
+}
+
+impl DisplayContent {
+    pub fn message(content: Content, priority: Priority, timeout_ms: usize) -> DisplayContent {
+        DisplayContent {
+            content,
+            blink: false,
+            priority,
+            timeout_ms,
+        }
+    }
+    pub fn blinking(self) -> DisplayContent {
+        DisplayContent { blink: true, ..self }
+    }
+}
+
+// For other tasks to post messages to the screen
+pub type DisplaySignal = Signal<CriticalSectionRawMutex, DisplayContent>;
+
+pub struct Screen {
+    base: Content,
+    message: Option<(DisplayContent, usize)>, // And the sample number it expires
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen {
+            base: Content::Blank,
+            message: None,
+        }
+    }
+    // Set what to show when there is no message
+    pub fn set_base(&mut self, content: Content) {
+        self.base = content;
+    }
+    // Show message m from sample number n, unless a more important one is up
+    // Return whether it is shown
+    pub fn post(&mut self, n: usize, m: DisplayContent) -> bool {
+        if let Some((current, until)) = &self.message {
+            if n < *until && m.priority < current.priority {
+                return false;
+            }
+        }
+        let until = n + m.timeout_ms;
+        self.message = Some((m, until));
+        true
+    }
+    // Show the message, or the base content, at sample number n
+    pub fn render(&mut self, d: &mut impl Display, n: usize) {
+        if matches!(self.message, Some((_, until)) if n >= until) {
+            self.message = None;
+        }
+        let (content, blink) = match &self.message {
+            Some((m, _)) => (&m.content, m.blink),
+            None => (&self.base, false),
+        };
+        if blink && (n / BLINK_MS) % 2 == 1 {
+            d.show_text("");
+            return;
+        }
+        match content {
+            Content::Blank => d.show_text(""),
+            Content::Number(x) => d.show_number(*x),
+            Content::Text(s) => d.show_text(s),
+            Content::Status(status) => d.show_status(*status),
+            Content::Error(code) => d.show_text(&format::<4>(format_args!("E{}", code))),
+            Content::Spinner => d.show_spinner(n),
+        }
+    }
+}
+
+// Return the base content showing the HR reading, at sample number n
+//   rate: tracked HR, 0 if none
+//   searching: there are beats, but no HR yet
+// Alarms alternate with everything else
+pub fn hr_content(n: usize, rate: u32, searching: bool, clipping: bool, alarm: Option<Alarm>) -> Content {
+    let alarm_phase = (n / ALARM_BLINK_MS) % 2 == 1;
+    match alarm {
+        Some(a) if alarm_phase => Content::Status(Status::Alarm(a)),
+        _ if clipping => Content::Status(Status::Clipping),
+        _ if rate > 0 => Content::Number(rate),
+        _ if searching => Content::Spinner,
+        _ => Content::Status(Status::NoSignal),
+    }
+}
+
+// Remembers what it was last told to show
+#[cfg(any(test, feature = "display-mock"))]
+pub struct Mock {
+    pub shown: String<32>,
+    pub brightness: u8,
+    pub writes: u32, // Number of calls that changed what is shown
+}
+
+#[cfg(any(test, feature = "display-mock"))]
+impl Mock {
+    pub fn new() -> Mock {
+        Mock {
+            shown: String::new(),
+            brightness: 0,
+            writes: 0,
+        }
+    }
+    fn show(&mut self, args: core::fmt::Arguments) {
+        let s: String<32> = format(args);
+        if s != self.shown {
+            self.shown = s;
+            self.writes += 1;
+        }
+    }
+}
+
+#[cfg(any(test, feature = "display-mock"))]
+impl Display for Mock {
+    fn show_number(&mut self, n: u32) {
+        self.show(format_args!("{}", n));
+    }
+    fn show_text(&mut self, s: &str) {
+        self.show(format_args!("{}", s));
+    }
+    fn set_brightness(&mut self, level: u8) {
+        self.brightness = level;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(screen: &mut Screen, n: usize) -> String<32> {
+        let mut d = Mock::new();
+        screen.render(&mut d, n);
+        d.shown
+    }
+
+    #[test]
+    fn priorities() {
+        let mut d = Mock::new();
+        let mut screen = Screen::new();
+        screen.set_base(hr_content(0, 0, false, false, None));
+        assert_eq!(shown(&mut screen, 0), "--");
+        screen.set_base(hr_content(0, 0, true, false, None));
+        assert_eq!(shown(&mut screen, 0), "|");
+        screen.set_base(hr_content(0, 72, false, false, None));
+        assert_eq!(shown(&mut screen, 0), "72");
+        screen.set_base(hr_content(0, 72, false, true, None));
+        assert_eq!(shown(&mut screen, 0), "Er");
+        // Alarm alternates with the reading
+        screen.set_base(hr_content(0, 190, false, false, Some(Alarm::High)));
+        assert_eq!(shown(&mut screen, 0), "190");
+        screen.set_base(hr_content(ALARM_BLINK_MS, 190, false, false, Some(Alarm::High)));
+        screen.render(&mut d, ALARM_BLINK_MS);
+        assert_eq!(d.shown, "Hi");
+        // Showing the same thing again isn't another write
+        let writes = d.writes;
+        screen.render(&mut d, ALARM_BLINK_MS + 1);
+        assert_eq!(d.writes, writes);
+    }
+
+    #[test]
+    fn messages() {
+        let mut screen = Screen::new();
+        screen.set_base(Content::Number(72));
+        let zone = Content::Status(Status::Zone(3));
+        assert!(screen.post(1000, DisplayContent::message(zone, Priority::Info, 2000)));
+        assert_eq!(shown(&mut screen, 1000), "Z3");
+        // Something more important takes over, and blinks
+        let lo = DisplayContent::message(Content::Status(Status::Alarm(Alarm::Low)), Priority::Alarm, 2000);
+        assert!(screen.post(1500, lo.blinking()));
+        assert_eq!(shown(&mut screen, 1500), "Lo");
+        assert_eq!(shown(&mut screen, 1500 + BLINK_MS), "");
+        // But not the other way around
+        let e2 = DisplayContent::message(Content::Error(2), Priority::Warning, 2000);
+        assert!(!screen.post(2000, e2.clone()));
+        assert_eq!(shown(&mut screen, 3000), "Lo");
+        // Then back to the HR
+        assert_eq!(shown(&mut screen, 3500), "72");
+        assert!(screen.post(4000, e2));
+        assert_eq!(shown(&mut screen, 4000), "E2");
+        assert!(screen.post(6000, DisplayContent::message(Content::Spinner, Priority::Info, 1000)));
+        assert_eq!(shown(&mut screen, 6000), "|");
+        assert_eq!(shown(&mut screen, 6000 + SPINNER_MS), "/");
+    }
+}
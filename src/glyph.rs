@@ -0,0 +1,148 @@
+// glyph: 14-segment glyphs as bitmasks
+//
+// A glyph is a u16 with one bit per segment, a..n as laid out in c5412.rs.
+// Fonts, custom glyphs and animation frames are then just data, and a
+// renderer applies a glyph to anything implementing Segments, eg. the GPIOs
+// of a display, or an array in a test.
+
+pub type Glyph = u16;
+
+pub const SEGMENTS: usize = 14;
+
+pub const A: Glyph = 1 << 0;
+pub const B: Glyph = 1 << 1;
+pub const C: Glyph = 1 << 2;
+pub const D: Glyph = 1 << 3;
+pub const E: Glyph = 1 << 4;
+pub const F: Glyph = 1 << 5;
+pub const G: Glyph = 1 << 6;
+pub const H: Glyph = 1 << 7;
+pub const I: Glyph = 1 << 8;
+pub const J: Glyph = 1 << 9;
+pub const K: Glyph = 1 << 10;
+pub const L: Glyph = 1 << 11;
+pub const M: Glyph = 1 << 12;
+pub const N: Glyph = 1 << 13;
+
+pub const BLANK: Glyph = 0;
+pub const DEGREE: Glyph = A | B | F | J | N;
+
+// ASCII ' ' to '_', which covers digits, upper case and the symbols we need
+const FIRST: u8 = b' ';
+#[rustfmt::skip]
+const FONT: [Glyph; 64] = [
+    BLANK,                      // ' '
+    0, 0, 0, 0, 0, 0, 0,        // ! " # $ % & '
+    0, 0, 0, 0, 0,              // ( ) * + ,
+    J | N,                      // '-'
+    0,                          // .
+    I | K,                      // '/'
+    A | B | C | D | E | F,      // '0'
+    B | C,                      // '1'
+    A | B | D | E | J | N,      // '2'
+    A | B | C | D | J | N,      // '3'
+    B | C | F | J | N,          // '4'
+    A | C | D | F | J | N,      // '5'
+    A | C | D | E | F | J | N,  // '6'
+    A | B | C,                  // '7'
+    A | B | C | D | E | F | J | N, // '8'
+    A | B | C | D | F | J | N,  // '9'
+    0, 0, 0, 0, 0,              // : ; < = >
+    A | B | N | L,              // '?'
+    0,                          // @
+    A | B | C | E | F | J | N,  // 'A'
+    A | B | C | D | H | L | N,  // 'B'
+    A | D | E | F,              // 'C'
+    A | B | C | D | H | L,      // 'D'
+    A | D | E | F | J,          // 'E'
+    A | E | F | J,              // 'F'
+    A | C | D | E | F | N,      // 'G'
+    B | C | E | F | J | N,      // 'H'
+    A | D | H | L,              // 'I'
+    B | C | D | E,              // 'J'
+    E | F | J | I | M,          // 'K'
+    D | E | F,                  // 'L'
+    B | C | E | F | G | I,      // 'M'
+    B | C | E | F | G | M,      // 'N'
+    A | B | C | D | E | F,      // 'O'
+    A | B | E | F | J | N,      // 'P'
+    A | B | C | D | E | F | M,  // 'Q'
+    A | B | E | F | J | N | M,  // 'R'
+    A | C | D | F | J | N,      // 'S'
+    A | H | L,                  // 'T'
+    B | C | D | E | F,          // 'U'
+    E | F | K | I,              // 'V'
+    B | C | E | F | K | M,      // 'W'
+    G | I | K | M,              // 'X'
+    G | I | L,                  // 'Y'
+    A | D | I | K,              // 'Z'
+    0,                          // [
+    G | M,                      // '\\'
+    0, 0,                       // ] ^
+    D,                          // '_'
+];
+
+// Return the glyph for ch
+// Lower case is shown as upper case, and anything unknown as blank
+pub fn font(ch: char) -> Glyph {
+    match ch {
+        '°' => return DEGREE,
+        '|' => return H | L,
+        _ => {}
+    }
+    let ch = ch.to_ascii_uppercase();
+    if !ch.is_ascii() || (ch as u8) < FIRST {
+        return BLANK;
+    }
+    FONT.get((ch as u8 - FIRST) as usize).copied().unwrap_or(BLANK)
+}
+
+// Something with 14 segments that can be turned on and off
+pub trait Segments {
+    fn set(&mut self, seg: usize, on: bool);
+}
+
+// Set every segment of out on or off to show glyph g
+pub fn render(g: Glyph, out: &mut impl Segments) {
+    for seg in 0..SEGMENTS {
+        out.set(seg, g & (1 << seg) != 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Mock([bool; SEGMENTS]);
+
+    impl Segments for Mock {
+        fn set(&mut self, seg: usize, on: bool) {
+            self.0[seg] = on;
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        assert_eq!(font('8'), A | B | C | D | E | F | J | N);
+        assert_eq!(font('h'), font('H'));
+        assert_eq!(font('-'), J | N);
+        assert_eq!(font('_'), D);
+        assert_eq!(font('°'), DEGREE);
+        assert_eq!(font('~'), BLANK);
+        assert_eq!(font('\n'), BLANK);
+        // Everything we need to show has a glyph
+        for ch in "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-_?|/\\".chars() {
+            assert_ne!(font(ch), BLANK);
+        }
+    }
+
+    #[test]
+    fn renders() {
+        let mut mock = Mock([true; SEGMENTS]);
+        render(font('1'), &mut mock);
+        assert_eq!(mock.0.iter().filter(|on| **on).count(), 2);
+        assert!(mock.0[1] && mock.0[2]);
+        render((1 << SEGMENTS) - 1, &mut mock);
+        assert!(mock.0.iter().all(|on| *on));
+    }
+}
@@ -0,0 +1,105 @@
+// hd44780: 16x2 character LCD backend
+//
+// Driven 4 bits at a time over 6 GPIOs (RS, E, D4..D7), with R/W tied low, so
+// we can't read the busy flag and just wait out each command instead.  That
+// blocks the calling task, so the first line is only rewritten when what it
+// shows changes, which takes about 1ms.
+//
+// The display's contrast is set with a pot, and there is no backlight control,
+// so brightness is ignored.
+
+use embassy_stm32::gpio::AnyPin;
+use embassy_stm32::gpio::Level::{High, Low};
+use embassy_stm32::gpio::Output;
+use embassy_time::{block_for, Duration};
+use heapless::String;
+
+use crate::display::{self, Display, Status};
+use crate::zones::Alarm;
+
+const WIDTH: usize = 16;
+
+pub struct Hd44780 {
+    rs: Output<'static, AnyPin>,
+    e: Output<'static, AnyPin>,
+    d: [Output<'static, AnyPin>; 4], // D4..D7
+    shown: String<WIDTH>,
+}
+
+impl Hd44780 {
+    // Initialize the LCD: 4-bit interface, 2 lines, display on, cursor off
+    pub fn new(rs: Output<'static, AnyPin>, e: Output<'static, AnyPin>, d: [Output<'static, AnyPin>; 4]) -> Hd44780 {
+        let mut lcd = Hd44780 {
+            rs,
+            e,
+            d,
+            shown: String::new(),
+        };
+        // Reset sequence from the datasheet, to get into 4-bit mode from any state
+        block_for(Duration::from_millis(50));
+        lcd.rs.set_low();
+        for wait_us in [4100, 100, 100] {
+            lcd.nibble(0x3);
+            block_for(Duration::from_micros(wait_us));
+        }
+        lcd.nibble(0x2);
+        block_for(Duration::from_micros(100));
+        lcd.command(0x28); // Function set: 4-bit, 2 lines, 5x8 font
+        lcd.command(0x0c); // Display on, no cursor
+        lcd.command(0x01); // Clear
+        block_for(Duration::from_millis(2));
+        lcd.command(0x06); // Entry mode: move right
+        lcd
+    }
+    fn nibble(&mut self, x: u8) {
+        for (i, d) in self.d.iter_mut().enumerate() {
+            d.set_level(if x & (1 << i) != 0 { High } else { Low });
+        }
+        self.e.set_high();
+        block_for(Duration::from_micros(1));
+        self.e.set_low();
+    }
+    fn byte(&mut self, x: u8) {
+        self.nibble(x >> 4);
+        self.nibble(x & 0xf);
+        block_for(Duration::from_micros(50));
+    }
+    fn command(&mut self, x: u8) {
+        self.rs.set_low();
+        self.byte(x);
+    }
+    // Show s on the first line, padded with spaces, if it isn't already
+    fn show(&mut self, args: core::fmt::Arguments) {
+        let s: String<WIDTH> = display::format(args);
+        if s == self.shown {
+            return;
+        }
+        self.command(0x80); // Start of first line
+        self.rs.set_high();
+        for i in 0..WIDTH {
+            // The LCD's character set matches ASCII, but not beyond it
+            let c = s.as_bytes().get(i).copied().filter(|c| c.is_ascii()).unwrap_or(b' ');
+            self.byte(c);
+        }
+        self.shown = s;
+    }
+}
+
+impl Display for Hd44780 {
+    fn show_number(&mut self, n: u32) {
+        self.show(format_args!("HR {} bpm", n));
+    }
+    fn show_text(&mut self, s: &str) {
+        self.show(format_args!("{}", s));
+    }
+    fn show_status(&mut self, status: Status) {
+        match status {
+            Status::NoSignal => self.show(format_args!("No pulse")),
+            Status::Clipping => self.show(format_args!("Sensor saturated")),
+            Status::Zone(z) => self.show(format_args!("Zone {}", z)),
+            Status::Alarm(Alarm::High) => self.show(format_args!("HR HIGH")),
+            Status::Alarm(Alarm::Low) => self.show(format_args!("HR LOW")),
+        }
+    }
+    fn set_brightness(&mut self, _level: u8) {}
+}
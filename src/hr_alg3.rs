@@ -1,28 +1,83 @@
 // hr_alg3: Heartrate Algorithm #3, including processing task
 
+use libm::fabs;
 use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
 
-const CRAZY_HI: u32 = 3000;
-const CRAZY_LO: u32 = 1000;
+use crate::apg::{Apg, ApgWaves};
+use crate::crazy::CrazyWindow;
+use crate::hr_track::{BeatFit, IbiTracker};
+use crate::template::{self, Template};
+
 const DC_ALPHA: f64 = 1.0 / 1000.0;
+const DC_ALPHA_FAST: f64 = 1.0 / 100.0; // Used for a while after an artifact
+const FAST_EMA_ALPHA: f64 = 1.0 / 200.0; // Short term mean, seeds baseline after an artifact
+const SLEW_ALPHA: f64 = 1.0 / 100.0; // Smooths |x - fast_ema| to judge when motion has stopped
+const ARTIFACT_MIN: usize = 100; // Crazy samples in a row it takes to be an artifact worth reacquiring from
+const SETTLE: usize = 200; // Calm samples before declaring the artifact over
+const FAST_SAMPLES: usize = 1500; // How long to use DC_ALPHA_FAST after reacquiring
 const LP_ALPHA: f64 = 1.0 / 100.0;
 const THRESHOLD_ALPHA_UP: f64 = 1.0 / 100.0;
 const THRESHOLD_ALPHA_DN: f64 = 1.0 / 2000.0;
 const PEAK_DELAY: usize = 200;
+// ADC is 12 bits, 16x oversampled without shifting, so full scale is 4095*16
+const ADC_FULL_SCALE: u32 = 4095 * 16;
+const CLIP_MARGIN: u32 = 64; // Samples this close to either rail are pinned
+const CLIP_MIN_RUN: usize = 20; // Pinned samples in a row to call it clipping
+const REFRACTORY_FRAC: f64 = 0.6; // Secondary peaks come this soon after main, relative to IBI
+const DICROTIC_MAX: f64 = 500.0; // But never later than this, so premature beats get through
+const SECONDARY_RATIO: f64 = 0.8; // Secondary peaks are smaller than main, relative to its amplitude
 
 const ABOVE_SIZE: usize = 200;
 
+// Per-beat waveform features, computed from the above window and the lowest
+//   sample (foot) seen between the previous window and this one.
+// Note the above window starts at the threshold crossing, so the half height
+//   point on the rising edge may be before the window.  Width and area only
+//   count what is in the window.
+#[derive(Clone, Copy, Default)]
+pub struct BeatFeatures {
+    pub amplitude: f64,  // Peak minus foot, counts
+    pub perfusion: f64,  // Amplitude relative to DC filter, percent (AC/DC)
+    pub rise_ms: usize,  // Foot to peak
+    pub width_ms: usize, // Samples at or above half height around the peak
+    pub area: f64,       // Sum of samples above the foot in the window, counts*ms
+}
+
 pub struct Hr {
     dc_ema: f64,        // DC filter
     lp_ema: f64,        // Low Pass filter
     threshold_ema: f64, // Asymmetric filter
+    crazy: CrazyWindow, // Motion artifact window around dc_ema
+    fast_ema: f64,      // Short term mean for reseeding baseline
+    slew_ema: f64,      // Short term activity around fast_ema
+    artifact_n: usize,  // Run of crazy samples, held once it reaches ARTIFACT_MIN
+    calm_n: usize,      // Consecutive calm samples
+    fast_dc_n: usize,   // Samples left to use DC_ALPHA_FAST
+    reacquired: u32,    // Times baseline was reacquired after an artifact
+    clip_run: usize,    // Consecutive samples pinned at a rail
+    clip_events: u32,   // Times clipping started
+    clip_total: usize,  // Total samples spent clipping
     n: usize,           // Monotonic counter of calls to `tick`
     state: u8,
     timer: usize,
     above_pts: ConstGenericRingBuffer<u32, ABOVE_SIZE>,
+    foot: u32,     // Lowest sample since the last above window
+    foot_n: usize, // When foot happened
+    features: BeatFeatures,
+    apg: Apg,
+    apg_waves: Option<ApgWaves>,
+    template: Template,
+    quality: Option<(usize, f64)>, // Template correlation of a recent beat
 
     last_peak_n: usize,
+    last_peak_amp: f64, // Height of last peak above DC filter
+    last_ibi: usize,    // Samples between last two peaks
+    last_clean: bool,   // No crazy samples between last two peaks
+    clean: bool,        // No crazy samples since last peak
     hr: f64,
+    tracker: IbiTracker,
+    fit: BeatFit,
+    secondary_rejects: u32, // Peaks discarded as dicrotic notch or ringing
 }
 
 impl Hr {
@@ -32,12 +87,36 @@ impl Hr {
             dc_ema: yc as f64,
             lp_ema: yc as f64,
             threshold_ema: yc as f64,
+            crazy: CrazyWindow::new(),
+            fast_ema: yc as f64,
+            slew_ema: 0.0,
+            artifact_n: 0,
+            calm_n: 0,
+            fast_dc_n: 0,
+            reacquired: 0,
+            clip_run: 0,
+            clip_events: 0,
+            clip_total: 0,
             n: 0,
             state: 0,
             timer: 0,
             above_pts: ConstGenericRingBuffer::<u32, ABOVE_SIZE>::new(),
+            foot: u32::MAX,
+            foot_n: 0,
+            features: BeatFeatures::default(),
+            apg: Apg::new(),
+            apg_waves: None,
+            template: Template::new(),
+            quality: None,
             last_peak_n: 0,
+            last_peak_amp: 0.0,
+            last_ibi: 0,
+            last_clean: false,
+            clean: false,
             hr: 0.0,
+            tracker: IbiTracker::new(),
+            fit: BeatFit::Start,
+            secondary_rejects: 0,
         }
     }
     // Process one sample; output a mess of things....
@@ -53,8 +132,28 @@ impl Hr {
     pub fn tick(&mut self, lp: bool, raw_sample: u32) -> (usize, u32, u8, u8) {
         let mut hr_update_flag: u8 = 0;
 
+        // A sample pinned at a rail carries no pulse information at all
+        let pinned = raw_sample <= CLIP_MARGIN || raw_sample >= ADC_FULL_SCALE - CLIP_MARGIN;
+        if pinned {
+            self.clip_run += 1;
+            if self.clip_run == CLIP_MIN_RUN {
+                self.clip_events += 1;
+                self.clip_total += CLIP_MIN_RUN;
+            } else if self.clip_run > CLIP_MIN_RUN {
+                self.clip_total += 1;
+            }
+        } else {
+            self.clip_run = 0;
+        }
+
         let fx = raw_sample as f64;
-        self.dc_ema += (fx - self.dc_ema) * DC_ALPHA;
+        if self.fast_dc_n > 0 {
+            self.fast_dc_n -= 1;
+            self.dc_ema += (fx - self.dc_ema) * DC_ALPHA_FAST;
+        } else {
+            self.dc_ema += (fx - self.dc_ema) * DC_ALPHA;
+        }
+        self.reacquire(fx);
 
         let (x, fx) = if lp {
             self.lp_ema += (fx - self.lp_ema) * LP_ALPHA;
@@ -63,10 +162,20 @@ impl Hr {
             (raw_sample, fx)
         };
 
+        if let Some(waves) = self.apg.push(self.n, fx) {
+            self.apg_waves = Some(waves);
+        }
+        if let Some(quality) = self.template.push(self.n, fx) {
+            self.quality = Some(quality);
+        }
+
         let yc: u32 = self.dc_ema as u32;
-        let y0: u32 = yc - CRAZY_LO;
-        let y1: u32 = yc + CRAZY_HI;
-        if y0 < x && x < y1 {
+        let (crazy_lo, crazy_hi) = self.crazy.window();
+        let y0: u32 = yc.saturating_sub(crazy_lo);
+        let y1: u32 = yc + crazy_hi;
+        let sane = y0 < x && x < y1 && !pinned;
+        self.crazy.update(fx - self.dc_ema, !sane);
+        if sane {
             if self.threshold_ema < fx {
                 self.threshold_ema += (fx - self.threshold_ema) * THRESHOLD_ALPHA_UP;
                 if self.state == 0 && self.timer >= PEAK_DELAY {
@@ -77,31 +186,70 @@ impl Hr {
             } else {
                 self.threshold_ema += (fx - self.threshold_ema) * THRESHOLD_ALPHA_DN;
                 if self.state == 1 && self.timer >= PEAK_DELAY {
-                    self.update_hr(self.n - PEAK_DELAY);
-                    hr_update_flag = 1;
+                    if self.update_hr(self.n - PEAK_DELAY).is_some() {
+                        hr_update_flag = 1;
+                    }
                     self.state = 0;
                     self.timer = 0;
+                    self.foot = u32::MAX;
                 }
             }
+            // Isolated spikes are not an artifact; only a long enough run is
+            if self.artifact_n < ARTIFACT_MIN {
+                self.artifact_n = 0;
+            }
             if self.state == 1 {
                 self.above_pts.push(x);
+            } else if x <= self.foot {
+                // Latest of equal minimums, ie. closest to the upstroke
+                self.foot = x;
+                self.foot_n = self.n;
             }
         } else {
             // Crazy value, reset state machine
             self.state = 0;
             self.timer = 0;
+            self.clean = false;
+            self.artifact_n += 1;
+            self.apg.cancel();
+            self.template.cancel();
         }
+        self.tracker.coast(self.n);
         self.n += 1;
         self.timer += 1;
 
         // Return Tick count, filtered input, state, hr_update_flag
         (self.n, x, self.state, hr_update_flag)
     }
+    // Called internally every tick to recover quickly from motion artifacts
+    //   After finger motion, dc_ema (and threshold_ema) can be off by more than
+    //   the crazy window, and with DC_ALPHA it takes seconds to get back.
+    //   Once the raw signal has been calm for a while after a long enough run of
+    //   crazy samples, reseed both from a short term mean, and let dc_ema run
+    //   fast for a bit to finish the job, before returning to the slow filter.
+    fn reacquire(&mut self, fx: f64) {
+        self.fast_ema += (fx - self.fast_ema) * FAST_EMA_ALPHA;
+        self.slew_ema += (fabs(fx - self.fast_ema) - self.slew_ema) * SLEW_ALPHA;
+        let (calm_level, _) = self.crazy.window();
+        if self.slew_ema < calm_level as f64 {
+            self.calm_n += 1;
+        } else {
+            self.calm_n = 0;
+        }
+        if self.artifact_n >= ARTIFACT_MIN && self.calm_n >= SETTLE {
+            self.dc_ema = self.fast_ema;
+            self.threshold_ema = self.fast_ema;
+            self.fast_dc_n = FAST_SAMPLES;
+            self.artifact_n = 0;
+            self.reacquired += 1;
+        }
+    }
     // Called internally when exiting state 1, that is, after the peak data has been
     //   collected.  Process it to find the max, and then the inter-peak distance
     //   and ultimately, the heart rate.
-    // Return the heartrate
-    fn update_hr(&mut self, start_n: usize) -> u32 {
+    // Return the heartrate, or None if the peak was discarded as a secondary peak.
+    //   The rate itself can be under 1 BPM, eg. for the first beat a minute in
+    fn update_hr(&mut self, start_n: usize) -> Option<f64> {
         // Search for peak in above data
         if self.above_pts.capacity() > 1 {
             let mut above_max: u32 = 0;
@@ -117,22 +265,351 @@ impl Hr {
             // Given when above_pts started, and above_ix, calc delta to last peak
             let this_peak_n = start_n + above_ix;
             let delta_n = this_peak_n - self.last_peak_n;
+            let amp = above_max as f64 - self.dc_ema;
+
+            // A smaller peak too soon after the last one is the dicrotic notch
+            //   or ringing from it, not another beat.  PEAK_DELAY alone is too
+            //   short to cover it at slow rates, so scale from the recent IBI.
+            //   Premature beats are small too, but later than the notch can be
+            if self.tracker.locked() {
+                let refractory = (REFRACTORY_FRAC * self.tracker.ibi().0).min(DICROTIC_MAX);
+                if (delta_n as f64) < refractory && amp < SECONDARY_RATIO * self.last_peak_amp {
+                    self.secondary_rejects += 1;
+                    return None;
+                }
+            }
             self.last_peak_n = this_peak_n;
+            self.last_peak_amp = amp;
+            self.last_ibi = delta_n;
+            self.last_clean = self.clean;
+            self.clean = true;
+            self.fit = self.tracker.beat(this_peak_n);
+            self.update_features(above_max, above_ix, this_peak_n);
+            self.template.beat(this_peak_n);
 
             // if delta > 200 && delta < 2000 {
             self.hr = 60000f64 / delta_n as f64;
 
-            self.hr as u32
+            Some(self.hr)
         } else {
-            0
+            None
         }
     }
+    // Called internally for each accepted beat to compute its waveform features
+    fn update_features(&mut self, above_max: u32, above_ix: usize, peak_n: usize) {
+        if self.foot == u32::MAX || self.foot >= above_max {
+            // No foot seen (eg. crazy samples reset things); can't say much
+            self.features = BeatFeatures::default();
+            return;
+        }
+        let foot = self.foot as f64;
+        let amplitude = above_max as f64 - foot;
+        let half = self.foot + (above_max - self.foot) / 2;
+        // Walk out from the peak in both directions while above half height
+        let left = self
+            .above_pts
+            .iter()
+            .take(above_ix)
+            .rev()
+            .take_while(|x| **x >= half)
+            .count();
+        let right = self
+            .above_pts
+            .iter()
+            .skip(above_ix + 1)
+            .take_while(|x| **x >= half)
+            .count();
+        let area = self
+            .above_pts
+            .iter()
+            .map(|x| *x as f64 - foot)
+            .filter(|y| *y > 0.0)
+            .sum();
+        self.features = BeatFeatures {
+            amplitude,
+            perfusion: 100.0 * amplitude / self.dc_ema,
+            rise_ms: peak_n - self.foot_n,
+            width_ms: left + 1 + right,
+            area,
+        };
+        self.apg.beat(self.foot_n, peak_n);
+    }
+    // Return waveform features of the most recent beat
+    pub fn features(&self) -> BeatFeatures {
+        self.features
+    }
+    // Return APG waves of a recent beat, once, when they become available
+    //   They arrive a few hundred ms after the beat itself; match on peak_n
+    pub fn take_apg(&mut self) -> Option<ApgWaves> {
+        self.apg_waves.take()
+    }
+    // Return (peak_n, correlation with the pulse template) of a recent beat,
+    //   once, when it becomes available.  Close to 1 is a clean beat
+    pub fn take_quality(&mut self) -> Option<(usize, f64)> {
+        self.quality.take()
+    }
+    // Return the ensemble averaged pulse template and number of beats in it
+    pub fn template(&self) -> (&[f64; template::LEN], u32) {
+        self.template.template()
+    }
     // Return most recent heartrate result
     pub fn hr(&self) -> f64 {
         self.hr
     }
+    // Return Kalman tracked heartrate and its variance (BPM, BPM^2), 0 if no lock
+    //   Unlike `hr`, this coasts through artifacts and ignores implausible beats
+    pub fn tracked(&self) -> (f64, f64) {
+        self.tracker.hr()
+    }
+    // Return most recent beat: (peak sample number, samples since previous peak,
+    //   true if no crazy samples came between the two peaks)
+    pub fn last_beat(&self) -> (usize, usize, bool) {
+        (self.last_peak_n, self.last_ibi, self.last_clean)
+    }
+    // Return how the tracker used the most recent beat
+    pub fn fit(&self) -> BeatFit {
+        self.fit
+    }
+    // Return number of peaks discarded as secondary (dicrotic) peaks since boot
+    pub fn secondary_rejects(&self) -> u32 {
+        self.secondary_rejects
+    }
+    // Return current crazy window (lo, hi) around the DC filter
+    pub fn crazy_window(&self) -> (u32, u32) {
+        self.crazy.window()
+    }
+    // Return number of times the baseline was reacquired after an artifact
+    pub fn reacquired(&self) -> u32 {
+        self.reacquired
+    }
+    // Return true while the ADC is saturated, ie. the sensor is clipping
+    pub fn clipping(&self) -> bool {
+        self.clip_run >= CLIP_MIN_RUN
+    }
+    // Return clipping statistics: (times clipping started, samples in current
+    //   run of clipping, total samples spent clipping)
+    pub fn clip_stats(&self) -> (u32, usize, usize) {
+        (
+            self.clip_events,
+            if self.clipping() { self.clip_run } else { 0 },
+            self.clip_total,
+        )
+    }
     // Return some internal values for debugging
     pub fn help(&self) -> (u32, u32) {
         (self.dc_ema as u32, self.threshold_ema as u32)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rhythm::{Rhythm, RhythmKind};
+    use libm::exp;
+
+    const IBI: usize = 800; // 75 BPM
+    const SLOW: usize = 1200; // 50 BPM, where 0.6 * IBI is well past the dicrotic notch
+
+    // Synthetic PPG: pulses on a 32768 baseline, with a finger motion artifact
+    //   from 20s to 22s, after which the AC coupled sensor is left 3000 counts
+    //   low and drifts back with a 5s time constant
+    fn replay(n: usize) -> u32 {
+        let t = (n % IBI) as f64;
+        let pulse = 600.0 * exp(-((t - 150.0) / 60.0) * ((t - 150.0) / 60.0));
+        let offset = match n {
+            0..=19999 => 0.0,
+            20000..=21999 => {
+                if (n / 100) % 2 == 0 {
+                    9000.0
+                } else {
+                    -9000.0
+                }
+            }
+            _ => -3000.0 * exp(-((n - 22000) as f64) / 5000.0),
+        };
+        (32768.0 + pulse + offset) as u32
+    }
+
+    // Return samples from end of artifact to first heartrate close to 75 BPM
+    fn recovery_time(hr: &mut Hr) -> usize {
+        for n in 0..40000 {
+            let (_, _, _, update) = hr.tick(false, replay(n));
+            if n >= 22000 && update != 0 && (hr.hr() - 75.0).abs() < 2.0 {
+                return n - 22000;
+            }
+        }
+        usize::MAX
+    }
+
+    // Synthetic PPG from a list of (peak sample, amplitude) pulses
+    fn pulses(n: usize, beats: &[(usize, f64)]) -> u32 {
+        let y: f64 = beats
+            .iter()
+            .map(|(p, a)| {
+                let t = (n as f64 - *p as f64) / 60.0;
+                a * exp(-t * t)
+            })
+            .sum();
+        (32768.0 + y) as u32
+    }
+
+    // Feed beats to Hr and, the way process_hr does, to Rhythm
+    // Return the rhythm events, the IBIs of the clean beats and secondary rejects
+    fn screen(beats: &[(usize, f64)], len: usize) -> (heapless::Vec<RhythmKind, 8>, heapless::Vec<usize, 64>, u32) {
+        let mut hr = Hr::new();
+        let mut rhythm = Rhythm::new();
+        let mut kinds = heapless::Vec::new();
+        let mut ibis = heapless::Vec::new();
+        for n in 0..len {
+            let (_, _, _, update) = hr.tick(false, pulses(n, beats));
+            if update != 0 {
+                let (peak_n, ibi, clean) = hr.last_beat();
+                if clean && hr.fit() != BeatFit::Start {
+                    _ = ibis.push(ibi);
+                    for e in rhythm.beat(peak_n, ibi).iter() {
+                        _ = kinds.push(e.kind);
+                    }
+                } else {
+                    rhythm.restart();
+                }
+            }
+        }
+        (kinds, ibis, hr.secondary_rejects())
+    }
+
+    #[test]
+    fn dicrotic_bump() {
+        // Once the tracker has locked, each beat gets a bump 450ms after it,
+        //   big enough to cross the threshold
+        let mut beats = heapless::Vec::<(usize, f64), 64>::new();
+        for k in 0..30 {
+            beats.push((150 + k * SLOW, 600.0)).unwrap();
+            if k >= 10 {
+                beats.push((600 + k * SLOW, 400.0)).unwrap();
+            }
+        }
+        let (kinds, ibis, rejects) = screen(&beats, 30 * SLOW);
+        assert!(kinds.is_empty());
+        assert!(rejects > 10);
+        assert!(ibis.len() > 20);
+        assert!(ibis.iter().all(|ibi| ibi.abs_diff(SLOW) < 10));
+    }
+
+    #[test]
+    fn premature_beat() {
+        // A small beat halfway, then the compensating pause to the next
+        //   regular beat.  That is inside 0.6 * IBI, but too late for a notch
+        let mut beats = heapless::Vec::<(usize, f64), 64>::new();
+        for k in 0..30 {
+            match k {
+                20 => beats.push((150 + k * SLOW - SLOW / 2, 400.0)).unwrap(),
+                _ => beats.push((150 + k * SLOW, 600.0)).unwrap(),
+            }
+        }
+        let (kinds, ibis, _) = screen(&beats, 30 * SLOW);
+        assert_eq!(kinds.as_slice(), &[RhythmKind::Premature]);
+        assert!(ibis.iter().any(|ibi| ibi.abs_diff(SLOW / 2) < 10));
+        assert!(ibis.iter().any(|ibi| ibi.abs_diff(3 * SLOW / 2) < 10));
+    }
+
+    #[test]
+    fn beat_features() {
+        let mut hr = Hr::new();
+        let mut quality = None;
+        for n in 0..15000 {
+            hr.tick(false, replay(n));
+            quality = hr.take_quality().or(quality);
+        }
+        assert!(quality.unwrap().1 > 0.99);
+        assert_eq!(hr.template().1, 16);
+        let f = hr.features();
+        assert!((f.amplitude - 600.0).abs() < 10.0);
+        assert!((f.perfusion - 100.0 * 600.0 / 32768.0).abs() < 0.1);
+        assert!(f.rise_ms > 140 && f.rise_ms < 160);
+        // Half height width of exp(-(t/60)^2) is 2*60*sqrt(ln 2)
+        assert!(f.width_ms > 90 && f.width_ms < 110);
+    }
+
+    #[test]
+    fn ignores_sparse_spikes() {
+        let mut hr = Hr::new();
+        for n in 0..80000 {
+            // One sample pinned at the top rail every 0.53s, out of step with the pulse
+            let x = if n % 530 == 529 {
+                ADC_FULL_SCALE
+            } else {
+                replay(n % 20000)
+            };
+            hr.tick(false, x);
+        }
+        assert_eq!(hr.reacquired(), 0);
+    }
+
+    #[test]
+    fn clips_at_rails() {
+        let mut hr = Hr::new();
+        let mut n = 0;
+        // Feed len samples of the pulse, or pinned at a rail, and count the beats
+        let mut feed = |hr: &mut Hr, len: usize, rail: Option<u32>| {
+            let mut beats = 0;
+            for _ in 0..len {
+                let x = rail.unwrap_or_else(|| replay(n % 20000));
+                let (_, _, _, update) = hr.tick(false, x);
+                if update != 0 && (hr.hr() - 75.0).abs() < 2.0 {
+                    beats += 1;
+                }
+                n += 1;
+            }
+            beats
+        };
+        assert!(feed(&mut hr, 15000, None) > 10);
+        for rail in [0, ADC_FULL_SCALE] {
+            let (events, _, total) = hr.clip_stats();
+            // A short run at the rail is not clipping yet
+            feed(&mut hr, CLIP_MIN_RUN - 1, Some(rail));
+            assert!(!hr.clipping());
+            assert_eq!(hr.clip_stats(), (events, 0, total));
+            feed(&mut hr, 100, None);
+            // A long one is, and counts from its first pinned sample
+            feed(&mut hr, CLIP_MIN_RUN, Some(rail));
+            assert!(hr.clipping());
+            assert_eq!(hr.clip_stats(), (events + 1, CLIP_MIN_RUN, total + CLIP_MIN_RUN));
+            feed(&mut hr, 500 - CLIP_MIN_RUN, Some(rail));
+            assert!(hr.clipping());
+            assert_eq!(hr.clip_stats(), (events + 1, 500, total + 500));
+            // It clears as soon as the samples leave the rail, and the pulse
+            //   is picked up again
+            feed(&mut hr, 1, None);
+            assert!(!hr.clipping());
+            assert_eq!(hr.clip_stats(), (events + 1, 0, total + 500));
+            assert!(feed(&mut hr, 8000, None) > 5);
+        }
+        assert_eq!(hr.clip_stats(), (2, 0, 1000));
+    }
+
+    #[test]
+    fn first_beat_after_a_minute() {
+        // Nothing for over a minute, so the first beat's "rate" is under 1 BPM,
+        //   but it is still a beat, and the ones after it are on time
+        let mut hr = Hr::new();
+        let mut rates = heapless::Vec::<f64, 4>::new();
+        for n in 0..70000 {
+            let x = if n < 61000 { 32768 } else { replay(n - 61000) };
+            let (_, _, _, update) = hr.tick(false, x);
+            if update != 0 {
+                _ = rates.push(hr.hr());
+            }
+        }
+        assert!(rates[0] < 1.0);
+        assert!((rates[1] - 75.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn reacquires_after_artifact() {
+        let mut hr = Hr::new();
+        let t = recovery_time(&mut hr);
+        assert_eq!(hr.reacquired(), 1);
+        // Without reacquiring, the slow baseline takes about 4.7s
+        assert!(t < 3000, "recovery took {} samples", t);
+    }
+}
@@ -0,0 +1,203 @@
+// hr_track: Kalman tracking of heart rate across artifacts
+//
+// A scalar Kalman filter whose state is the inter-beat interval (IBI) in
+// samples, which at 1kHz is also milliseconds.  Every detected beat is a
+// measurement of the interval since the last accepted beat.
+//
+// * Gaps: if a beat is missed (crazy samples, weak pulse), the interval
+//   measured is close to a whole multiple of the predicted IBI.  It is divided
+//   back down rather than being taken as a very slow heart rate.
+// * Coasting: while no beats arrive, the predicted beat time steps forward one
+//   IBI at a time and the variance grows each step, so the tracked HR keeps
+//   being reported, but with less and less confidence.  After MAX_COAST
+//   missing beats the lock is dropped.
+// * Gating: a measurement too far from the prediction, given the current
+//   variance, is rejected (eg. a double count from ringing).  Too many rejects
+//   in a row and we assume the rhythm really changed, and start over.
+
+use libm::round;
+
+const MIN_IBI: f64 = 250.0; // 240 BPM
+const MAX_IBI: f64 = 2000.0; // 30 BPM
+const INIT_VAR: f64 = 100.0 * 100.0; // Variance of first interval, samples^2
+const Q_BEAT: f64 = 20.0 * 20.0; // Process noise added per beat
+const Q_COAST: f64 = 60.0 * 60.0; // Process noise added per missed beat
+const R_BEAT: f64 = 40.0 * 40.0; // Measurement noise of a peak to peak interval
+const GATE: f64 = 3.0; // Innovation gate, in standard deviations
+const MAX_COAST: u32 = 4; // Missed beats tolerated before dropping lock
+const MAX_REJECTS: u32 = 3; // Consecutive rejects before starting over
+
+// What the tracker made of a beat handed to `beat`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BeatFit {
+    Start,       // First beat, or first after losing lock: no interval yet
+    Init,        // Second beat, interval used to initialize the filter
+    Accepted,    // Interval agreed with prediction and updated the filter
+    Missed(u32), // Accepted, but spanned this many missed beats
+    Rejected,    // Innovation failed the gate, beat ignored
+}
+
+pub struct IbiTracker {
+    ibi: f64,      // Estimated inter-beat interval, samples
+    var: f64,      // Variance of the estimate, samples^2
+    last_n: usize, // Sample number of last accepted beat
+    next_n: usize, // Predicted sample number of next beat
+    started: bool, // Have a last_n to measure from
+    locked: bool,  // Have an estimate of ibi
+    coasted: u32,  // Beats predicted but not seen since last accepted beat
+    rejects: u32,  // Consecutive rejected beats
+}
+
+impl IbiTracker {
+    pub fn new() -> IbiTracker {
+        IbiTracker {
+            ibi: 0.0,
+            var: 0.0,
+            last_n: 0,
+            next_n: 0,
+            started: false,
+            locked: false,
+            coasted: 0,
+            rejects: 0,
+        }
+    }
+    pub fn reset(&mut self) {
+        *self = IbiTracker::new();
+    }
+    // Feed a detected beat (peak) at sample number n
+    // Return how it was used
+    pub fn beat(&mut self, n: usize) -> BeatFit {
+        if !self.started {
+            self.restart(n);
+            return BeatFit::Start;
+        }
+        let delta = (n - self.last_n) as f64;
+        if !self.locked {
+            if (MIN_IBI..=MAX_IBI).contains(&delta) {
+                self.ibi = delta;
+                self.var = INIT_VAR;
+                self.accept(n);
+                return BeatFit::Init;
+            }
+            self.restart(n);
+            return BeatFit::Start;
+        }
+
+        // Assume a long interval is a whole number of beats, some missed
+        let k = round(delta / self.ibi).clamp(1.0, (MAX_COAST + 1) as f64);
+        let z = delta / k;
+        // Coasting already grew the variance for the beats it stepped over
+        let uncoasted = (k - 1.0 - self.coasted as f64).max(0.0);
+        let var_p = self.var + Q_BEAT + Q_COAST * uncoasted;
+        let innovation = z - self.ibi;
+        let s = var_p + R_BEAT;
+        if innovation * innovation > GATE * GATE * s || !(MIN_IBI..=MAX_IBI).contains(&z) {
+            self.rejects += 1;
+            if self.rejects >= MAX_REJECTS {
+                self.restart(n);
+                return BeatFit::Start;
+            }
+            return BeatFit::Rejected;
+        }
+        let gain = var_p / s;
+        self.ibi += gain * innovation;
+        self.var = (1.0 - gain) * var_p;
+        self.accept(n);
+        if k > 1.0 {
+            BeatFit::Missed(k as u32 - 1)
+        } else {
+            BeatFit::Accepted
+        }
+    }
+    // Call once per sample: steps the prediction forward through missing beats
+    // Cheap unless a beat is overdue
+    pub fn coast(&mut self, n: usize) {
+        if self.locked && n > self.next_n + (self.ibi / 2.0) as usize {
+            self.next_n += self.ibi as usize;
+            self.var += Q_COAST;
+            self.coasted += 1;
+            if self.coasted > MAX_COAST {
+                self.locked = false;
+                self.started = false;
+            }
+        }
+    }
+    fn accept(&mut self, n: usize) {
+        self.last_n = n;
+        self.next_n = n + self.ibi as usize;
+        self.locked = true;
+        self.coasted = 0;
+        self.rejects = 0;
+    }
+    fn restart(&mut self, n: usize) {
+        self.last_n = n;
+        self.started = true;
+        self.locked = false;
+        self.coasted = 0;
+        self.rejects = 0;
+    }
+    // True if the filter has an interval estimate
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+    // True if the last predicted beat(s) did not show up
+    pub fn coasting(&self) -> bool {
+        self.locked && self.coasted > 0
+    }
+    // Predicted sample number of the next beat
+    pub fn next_beat(&self) -> usize {
+        self.next_n
+    }
+    // Tracked inter-beat interval and its variance, in samples and samples^2
+    pub fn ibi(&self) -> (f64, f64) {
+        (self.ibi, self.var)
+    }
+    // Tracked heart rate and its variance, in BPM and BPM^2, or 0 if not locked
+    //   Variance is carried over from the IBI by linearizing 60000/ibi
+    pub fn hr(&self) -> (f64, f64) {
+        if self.locked {
+            let hr = 60000.0 / self.ibi;
+            let d = hr / self.ibi;
+            (hr, d * d * self.var)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_and_tracks() {
+        let mut t = IbiTracker::new();
+        assert_eq!(t.beat(1000), BeatFit::Start);
+        assert_eq!(t.beat(1800), BeatFit::Init);
+        for i in 2..20 {
+            assert_eq!(t.beat(1000 + i * 800), BeatFit::Accepted);
+        }
+        let (hr, var) = t.hr();
+        assert!((hr - 75.0).abs() < 0.5);
+        assert!(var < 25.0);
+    }
+
+    #[test]
+    fn gaps_and_double_counts() {
+        let mut t = IbiTracker::new();
+        for i in 0..10 {
+            t.beat(i * 800);
+        }
+        let (_, var0) = t.ibi();
+        // Ringing: extra peak 300 samples after a real one is rejected
+        assert_eq!(t.beat(9 * 800 + 300), BeatFit::Rejected);
+        // Three beats lost to an artifact, coasting grows the variance
+        for n in 9 * 800..12 * 800 + 500 {
+            t.coast(n);
+        }
+        assert!(t.coasting());
+        assert!(t.ibi().1 > var0);
+        assert_eq!(t.beat(13 * 800), BeatFit::Missed(3));
+        assert!((t.hr().0 - 75.0).abs() < 1.0);
+    }
+}
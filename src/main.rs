@@ -3,15 +3,18 @@
 #![feature(type_alias_impl_trait)]
 use core::sync::atomic::{AtomicU32, Ordering};
 
+use display::Display;
 #[allow(arithmetic_overflow)]
 // use defmt::*;
 use embassy_executor::Spawner;
 use embassy_stm32::adc::{Adc, Resolution};
 use embassy_stm32::gpio::Level::{High, Low};
 use embassy_stm32::gpio::{Input, Level, Output, Pull, Speed};
-use embassy_stm32::usart::{Config, UartTx};
+use embassy_stm32::usart::{Config, Uart};
+use embassy_stm32::{bind_interrupts, peripherals, usart};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
 use embassy_time::{Delay, Instant, Timer};
 use heapless::String;
 use static_cell::StaticCell;
@@ -29,6 +32,8 @@ enum HrDebugMode {
     Debug,          // Show that adc, now.as_millis and process_hr are all in lockstep
     Stats,          // Show lots of stuff including min/mean/max time in hr task
     DisplayOverrun, // Show display task overrun counter
+    Features,       // Show per-beat waveform features
+    Apg,            // Show per-beat second derivative (APG) waves
 }
 #[allow(dead_code)]
 #[derive(PartialEq, Eq)]
@@ -42,31 +47,100 @@ enum DebugMode {
 const DEBUG_MODE: DebugMode = DebugMode::DumpSamples;
 
 //
-// Things needed for 14-segment driver processing task
+// Things needed for the display
 //
 
+mod display;
+#[cfg(any(feature = "display-c5412", feature = "display-ssd1306"))]
+mod glyph;
+
+static DISPLAY_INST: StaticCell<display::Active> = StaticCell::new();
+
+// Async communication: messages from any task to show on the display
+static DISPLAY_SIGNAL: display::DisplaySignal = Signal::new();
+
+// 14-segment LED, refreshed by its own task
+#[cfg(feature = "display-c5412")]
 mod c5412;
 
+#[cfg(feature = "display-c5412")]
 static C5412PINS_INST: StaticCell<c5412::C5412Pins> = StaticCell::new();
 
-// Async communication: value to display, 0-99, to c5412 task
-static DISP_VALUE_ATOMIC: AtomicU32 = AtomicU32::new(0);
+// Async communication: what to display to c5412 task
+#[cfg(feature = "display-c5412")]
+static DISP_FRAMES_SIGNAL: c5412::FrameSignal = Signal::new();
+
+// Time per character of text scrolling through the LED's 2 digits
+#[cfg(feature = "display-c5412")]
+const DISP_SCROLL_MS: u64 = 300;
+
+// Character LCD
+#[cfg(feature = "display-hd44780")]
+mod hd44780;
+
+// I2C OLED
+#[cfg(feature = "display-ssd1306")]
+mod ssd1306;
+
+#[cfg(feature = "display-ssd1306")]
+bind_interrupts!(struct I2cIrqs {
+    I2C1_EV => embassy_stm32::i2c::InterruptHandler<peripherals::I2C1>;
+});
 
 //
 // Things needed for HR processing task
 //
 
+mod apg;
+mod crazy;
 mod hr_alg3;
+mod hr_track;
+mod multi;
+mod resp;
+mod rhythm;
+mod session;
+// Ready for when the red/IR sensor pair is fitted; not wired up yet
+#[allow(dead_code)]
+mod spo2;
+mod template;
+mod trend;
+mod zones;
+
+// Wearer's training zones and HR alarm limits
+const ZONE_CONFIG: zones::ZoneConfig = zones::ZoneConfig {
+    age: 40,
+    max_hr: 0.0, // Predict from age
+    rest_hr: 60.0,
+    alarm_lo: 40.0,
+    alarm_hi: 180.0,
+};
 
 // Async communication: ADC overrun detection.  Expect ADC_N == elapsed millis
 static ADC_N_ATOMIC: AtomicU32 = AtomicU32::new(0);
 
-// Async communication: ADC value from main (ADC) task to HR processing task
+// Number of sensors sampled together, each with its own HR processing
+const N_SENSORS: usize = 2;
+type SampleFrame = [u32; N_SENSORS];
+
+// Async communication: ADC values from main (ADC) task to HR processing task
 // Worst case seen was 150ms delay during one version of HR processing,
 // so sized channel to be somewhat larger, at 1kHz sample rate.
 // Note that if sending to the channel overruns, the ADC task will panic, so
 // it should be easyish to tune this value.
-static SAMPLE_CHANNEL: Channel<CriticalSectionRawMutex, u32, 200> = Channel::new();
+static SAMPLE_CHANNEL: Channel<CriticalSectionRawMutex, SampleFrame, 200> = Channel::new();
+
+//
+// Things needed for console command task
+//
+
+mod console;
+
+// Async communication: commands typed on the console, to HR processing task
+static COMMAND_CHANNEL: console::CommandChannel = Channel::new();
+
+bind_interrupts!(struct Irqs {
+    USART3 => usart::InterruptHandler<peripherals::USART3>;
+});
 
 //
 // Gymnastics to pass peripherals into tasks.
@@ -79,6 +153,10 @@ type UART =
     embassy_stm32::usart::UartTx<'static, embassy_stm32::peripherals::USART3, embassy_stm32::peripherals::DMA1_CH1>;
 static UART_INST: StaticCell<UART> = StaticCell::new();
 
+type UARTRX =
+    embassy_stm32::usart::UartRx<'static, embassy_stm32::peripherals::USART3, embassy_stm32::peripherals::DMA1_CH2>;
+static UARTRX_INST: StaticCell<UARTRX> = StaticCell::new();
+
 type LED1 = embassy_stm32::gpio::Output<'static, embassy_stm32::peripherals::PB0>;
 static LED1_INST: StaticCell<LED1> = StaticCell::new();
 
@@ -88,21 +166,48 @@ static LED3_INST: StaticCell<LED3> = StaticCell::new();
 type BUTTON1 = embassy_stm32::gpio::Input<'static, embassy_stm32::peripherals::PC13>;
 static BUTTON1_INST: StaticCell<BUTTON1> = StaticCell::new();
 
+#[cfg(feature = "display-ssd1306")]
+type I2C = embassy_stm32::i2c::I2c<'static, embassy_stm32::peripherals::I2C1>;
+#[cfg(feature = "display-ssd1306")]
+static I2C_INST: StaticCell<I2C> = StaticCell::new();
+
 // Heartrate computation task
-// Simply call hr::tick(sample) and output something based on results
+// Simply call hr::tick(sample) for each sensor and output something based on
+//   results from whichever sensor currently has the best signal
 #[embassy_executor::task]
 async fn process_hr(
     uart_ref: &'static mut UART,
-    led1_ref: &'static mut LED1, // Used to show pulse
-    led3_ref: &'static mut LED3, // Used to show "lp" flag for debugging
+    led1_ref: &'static mut LED1, // Used to show pulse, or alarm pattern
+    led3_ref: &'static mut LED3, // Used to show "lp" flag for debugging, or alarm pattern
     button1_ref: &'static mut BUTTON1,
-    display_value_atomic: &'static AtomicU32,
+    display_ref: &'static mut display::Active,
+    display_signal: &'static display::DisplaySignal,
+    command_channel: &'static console::CommandChannel,
 ) {
-    let mut msg: String<128> = String::new();
+    let mut msg: String<192> = String::new();
     msg.clear();
     core::fmt::write(&mut msg, format_args!("Boot\n")).unwrap();
     _ = (uart_ref).write(msg.as_bytes()).await;
-    let mut hr = hr_alg3::Hr::new();
+    let mut sensors = multi::Sensors::<N_SENSORS>::new();
+    let mut best = sensors.best();
+    let mut rhythm = rhythm::Rhythm::new();
+    let mut resp = resp::Resp::new();
+    let mut zones = zones::Zones::new(ZONE_CONFIG);
+    const ZONE_SHOW_MS: usize = 2000; // Show new zone number this long
+    const ALARM_SHOW_MS: usize = 2000; // Flash a new alarm this long
+    const SEARCH_MS: usize = 3000; // Searching for HR this long after a beat
+    let mut beat_n: Option<usize> = None; // Sample number of the latest beat
+    let mut screen = display::Screen::new();
+    let mut display_value = 0u32;
+    let mut brightness = 224u8; // 32 per step, starting at the top level
+    display_ref.set_brightness(brightness);
+    let mut session = session::Session::new();
+    let mut in_session = false;
+    let mut trend = trend::Trend::new();
+    let mut clipping = false;
+    let mut clip_start_n = 0usize;
+    let mut quality = 0f64; // Template correlation of most recent beat
+    let mut template_ix: Option<usize> = None; // Next template point to dump
     let mut count0 = 0u32;
     let mut proc_n0 = 0usize;
     let mut adc_n0 = ADC_N_ATOMIC.load(Ordering::Relaxed);
@@ -111,21 +216,161 @@ async fn process_hr(
     let mut s = Stats::new();
     ts.loop_tick();
     loop {
-        let sample = SAMPLE_CHANNEL.receive().await;
+        let frame = SAMPLE_CHANNEL.receive().await;
         ts.loop_tick();
         let now = Instant::now().as_micros();
         let adc_n = ADC_N_ATOMIC.load(Ordering::Relaxed);
         let lp = button1_ref.get_level() == Level::Low;
-        let count = c5412::get_count();
-        led3_ref.set_level(if !lp { High } else { Low });
-        let (proc_n, cooked_sample, state, hr_update) = hr.tick(lp, sample);
-        // If we got a heartrate update, reflect it on LED
+        let (count, overrun) = display_ref.refresh_stats();
+        let ticks = sensors.tick(lp, &frame);
+        let ptt = sensors.ptt();
+        let scores = *sensors.scores();
+        let switched = sensors.best() != best;
+        best = sensors.best();
+        let (proc_n, cooked_sample, state, hr_update) = ticks[best];
+        let hr = sensors.hr_mut(best);
+        if let Some((_, q)) = hr.take_quality() {
+            quality = q;
+        }
+        if let Ok(command) = command_channel.try_receive() {
+            match command {
+                console::Command::Template => template_ix = Some(0),
+                console::Command::Brighter => {
+                    brightness = brightness.saturating_add(32);
+                    display_ref.set_brightness(brightness);
+                }
+                console::Command::Dimmer => {
+                    brightness = brightness.saturating_sub(32);
+                    display_ref.set_brightness(brightness);
+                }
+                console::Command::LampTest => display_ref.lamp_test(),
+            }
+        }
+        // If we got a heartrate update, reflect it on display and check zones
+        //   Use the tracked rate, which holds steady through artifacts
+        let mut zone_events = zones::ZoneEvents::new();
+        if hr_update != 0 {
+            beat_n = Some(proc_n);
+            let (rate, _) = hr.tracked();
+            if rate > 0.0 {
+                display_ref.beat();
+                display_value = rate as u32;
+                zone_events = zones.update(proc_n, rate);
+            } else {
+                zones.restart();
+            }
+        }
+        // Announce zone changes and new alarms on the display for a while
+        for e in zone_events.iter() {
+            let m = match e {
+                zones::ZoneEvent::Zone(z) => {
+                    let zone = display::Content::Status(display::Status::Zone(*z));
+                    display::DisplayContent::message(zone, display::Priority::Info, ZONE_SHOW_MS)
+                }
+                zones::ZoneEvent::AlarmOn(a) => {
+                    let alarm = display::Content::Status(display::Status::Alarm(*a));
+                    display::DisplayContent::message(alarm, display::Priority::Alarm, ALARM_SHOW_MS).blinking()
+                }
+                zones::ZoneEvent::AlarmOff(_) => continue,
+            };
+            screen.post(proc_n, m);
+        }
+        if let Some(m) = display_signal.try_take() {
+            screen.post(proc_n, m);
+        }
+        // Show the tracked HR, or clipping, alarm etc. instead, under any message
+        let rate = if hr.tracked().0 > 0.0 { display_value } else { 0 };
+        let searching = matches!(beat_n, Some(b) if proc_n - b < SEARCH_MS);
+        screen.set_base(display::hr_content(
+            proc_n,
+            rate,
+            searching,
+            hr.clipping(),
+            zones.alarm(),
+        ));
+        screen.render(display_ref, proc_n);
+        match zones.leds(proc_n) {
+            Some((led1, led3)) => {
+                led1_ref.set_level(if led1 { High } else { Low });
+                led3_ref.set_level(if led3 { High } else { Low });
+            }
+            None => {
+                led1_ref.set_level(if state != 0 { High } else { Low });
+                led3_ref.set_level(if !lp { High } else { Low });
+            }
+        }
+        // Screen the rhythm and estimate breathing, but only across
+        //   uninterrupted pairs of beats
+        let mut rhythm_events = rhythm::RhythmEvents::new();
+        let mut resp_rate = None;
+        if switched {
+            rhythm.restart();
+            resp.restart();
+        }
         if hr_update != 0 {
-            display_value_atomic.store(hr.hr() as u32, Ordering::Relaxed);
+            let (peak_n, ibi, clean) = hr.last_beat();
+            if clean && hr.fit() != hr_track::BeatFit::Start {
+                rhythm_events = rhythm.beat(peak_n, ibi);
+                let (dc, _) = hr.help();
+                resp_rate = resp.beat(peak_n, dc as f64, hr.features().amplitude, ibi);
+            } else {
+                rhythm.restart();
+                resp.restart();
+            }
+        }
+        let valid_rate = if hr.clipping() { 0.0 } else { hr.tracked().0 };
+        let summary = session.tick(proc_n, valid_rate, valid_rate > 0.0, zones.zone());
+        let trend_event = trend.tick(proc_n, valid_rate);
+        // Session start and end are rare and the summary is lost otherwise, so
+        //   they go out in every mode, even between dumped samples
+        if session.active() && !in_session {
+            msg.clear();
+            core::fmt::write(&mut msg, format_args!("Session: start\n")).unwrap();
+            _ = uart_ref.write(msg.as_bytes()).await;
+        }
+        in_session = session.active();
+        if let Some(s) = summary {
+            msg.clear();
+            core::fmt::write(
+                &mut msg,
+                format_args!(
+                    "Session: end {}s hr={:.0}/{:.0}/{:.0} rest={:.0} valid={:.0}%\n",
+                    s.duration_s,
+                    s.min,
+                    s.mean,
+                    s.max,
+                    s.resting,
+                    s.valid * 100.0
+                ),
+            )
+            .unwrap();
+            _ = uart_ref.write(msg.as_bytes()).await;
+            msg.clear();
+            core::fmt::write(&mut msg, format_args!("Zones: {:?}s\n", s.zone_s)).unwrap();
+            _ = uart_ref.write(msg.as_bytes()).await;
+        }
+        // Dump the pulse template a point at a time, so we don't fall behind on
+        //   samples waiting for the UART. It was asked for, so it goes out in
+        //   every mode, holding off the sample and timing dumps until it's done
+        let dumping = template_ix.is_some();
+        if let Some(i) = template_ix {
+            if proc_n % 4 == 0 {
+                let (points, beats) = hr.template();
+                msg.clear();
+                if i == 0 {
+                    core::fmt::write(&mut msg, format_args!("Template: {} beats\n", beats)).unwrap();
+                }
+                let ms = (i * template::MS_PER_POINT) as i32 - template::HALF_MS as i32;
+                core::fmt::write(&mut msg, format_args!("T {} {:.1}\n", ms, points[i])).unwrap();
+                _ = uart_ref.write(msg.as_bytes()).await;
+                template_ix = if i + 1 < template::LEN { Some(i + 1) } else { None };
+            }
         }
-        led1_ref.set_level(if state != 0 { High } else { Low });
         match DEBUG_MODE {
             DebugMode::DumpTiming => {
+                if dumping {
+                    continue;
+                }
                 let dadc_n = adc_n - adc_n0;
                 let dnow = now - now0;
                 msg.clear();
@@ -137,6 +382,9 @@ async fn process_hr(
                 continue;
             }
             DebugMode::DumpSamples => {
+                if dumping {
+                    continue;
+                }
                 msg.clear();
                 core::fmt::write(
                     &mut msg,
@@ -166,11 +414,12 @@ async fn process_hr(
                             ts.reset();
                         }
                         HrDebugMode::Debug => {
+                            let (track, var) = hr.tracked();
                             core::fmt::write(
                                 &mut msg,
                                 format_args!(
-                                    "rate={:.2} refresh={:.2} dcount={} dproc={} dadc={} dnow={}\n",
-                                    rate, refresh, dcount, dproc_n, dadc_n, dnow
+                                    "ch={} rate={:.2} track={:.2} var={:.2} sec={} ptt={:.1} slope={:.1} refresh={:.2} dcount={} dproc={} dadc={} dnow={}\n",
+                                    best, rate, track, var, hr.secondary_rejects(), ptt.unwrap_or(0.0), trend.slope(60000).unwrap_or(0.0), refresh, dcount, dproc_n, dadc_n, dnow
                                 ),
                             )
                             .unwrap();
@@ -179,8 +428,22 @@ async fn process_hr(
                             let err = dadc_n as i32 - dproc_n as i32;
                             core::fmt::write(&mut msg, format_args!("{:.2} {:.2} {}\n", rate, refresh, err)).unwrap();
                         }
+                        HrDebugMode::Apg => {
+                            // Waves are shown on their own line once available
+                            core::fmt::write(&mut msg, format_args!("{:.2}\n", rate)).unwrap();
+                        }
+                        HrDebugMode::Features => {
+                            let f = hr.features();
+                            core::fmt::write(
+                                &mut msg,
+                                format_args!(
+                                    "{:.2} amp={:.0} pi={:.2} rise={} width={} area={:.0} q={:.2}\n",
+                                    rate, f.amplitude, f.perfusion, f.rise_ms, f.width_ms, f.area, quality
+                                ),
+                            )
+                            .unwrap();
+                        }
                         HrDebugMode::DisplayOverrun => {
-                            let overrun = c5412::get_overrun();
                             core::fmt::write(&mut msg, format_args!("{:.2} {:.2} {}\n", rate, refresh, overrun))
                                 .unwrap();
                         }
@@ -194,11 +457,117 @@ async fn process_hr(
             }
             DebugMode::None => {}
         }
+        if let Some(w) = hr.take_apg() {
+            if DEBUG_MODE == DebugMode::Hr(HrDebugMode::Apg) {
+                msg.clear();
+                core::fmt::write(
+                    &mut msg,
+                    format_args!(
+                        "Apg: {} a={:.1}@{} b={:.1}@{} c={:.1}@{} d={:.1}@{} e={:.1}@{} b/a={:.3} d/a={:.3}\n",
+                        w.peak_n,
+                        w.a.amp,
+                        w.a.ms,
+                        w.b.amp,
+                        w.b.ms,
+                        w.c.amp,
+                        w.c.ms,
+                        w.d.amp,
+                        w.d.ms,
+                        w.e.amp,
+                        w.e.ms,
+                        w.b_a(),
+                        w.d_a()
+                    ),
+                )
+                .unwrap();
+                _ = uart_ref.write(msg.as_bytes()).await;
+            }
+        }
+        if switched {
+            msg.clear();
+            core::fmt::write(&mut msg, format_args!("Sensor: {}\n", best)).unwrap();
+            _ = uart_ref.write(msg.as_bytes()).await;
+        }
+        // Tell the user the sensor is saturated, rather than just no pulse
+        if hr.clipping() != clipping {
+            clipping = hr.clipping();
+            let (events, run, _) = hr.clip_stats();
+            msg.clear();
+            if clipping {
+                clip_start_n = proc_n - run;
+                core::fmt::write(&mut msg, format_args!("Clip: start {}\n", events)).unwrap();
+            } else {
+                core::fmt::write(&mut msg, format_args!("Clip: end {}ms\n", proc_n - clip_start_n)).unwrap();
+            }
+            _ = uart_ref.write(msg.as_bytes()).await;
+        }
+        for e in rhythm_events.iter() {
+            msg.clear();
+            core::fmt::write(&mut msg, format_args!("Rhythm: {} {}\n", e.n, e.kind.name())).unwrap();
+            _ = uart_ref.write(msg.as_bytes()).await;
+        }
+        for e in zone_events.iter() {
+            msg.clear();
+            match e {
+                zones::ZoneEvent::Zone(z) => {
+                    let (lo, hi) = zones.zone_range(*z);
+                    core::fmt::write(&mut msg, format_args!("Zone: {} {:.0}-{:.0}\n", z, lo, hi)).unwrap();
+                }
+                zones::ZoneEvent::AlarmOn(a) => {
+                    let (rate, _) = hr.tracked();
+                    core::fmt::write(&mut msg, format_args!("Alarm: {} on {:.0}\n", a.name(), rate)).unwrap();
+                }
+                zones::ZoneEvent::AlarmOff(a) => {
+                    core::fmt::write(&mut msg, format_args!("Alarm: {} off\n", a.name())).unwrap();
+                }
+            }
+            _ = uart_ref.write(msg.as_bytes()).await;
+        }
+        if let Some(e) = trend_event {
+            msg.clear();
+            match e {
+                trend::TrendEvent::Peak(bpm) => core::fmt::write(&mut msg, format_args!("Trend: peak {:.0}\n", bpm)),
+                trend::TrendEvent::Hrr1(d) => core::fmt::write(&mut msg, format_args!("Trend: HRR 1min {:.0}\n", d)),
+                trend::TrendEvent::Hrr2(d) => core::fmt::write(&mut msg, format_args!("Trend: HRR 2min {:.0}\n", d)),
+            }
+            .unwrap();
+            _ = uart_ref.write(msg.as_bytes()).await;
+        }
+        if let Some(r) = resp_rate {
+            msg.clear();
+            core::fmt::write(
+                &mut msg,
+                format_args!(
+                    "Resp: {:.1} {} {:.1}/{:.1}/{:.1}\n",
+                    r.bpm,
+                    if r.good { "good" } else { "poor" },
+                    r.rates[0],
+                    r.rates[1],
+                    r.rates[2]
+                ),
+            )
+            .unwrap();
+            _ = uart_ref.write(msg.as_bytes()).await;
+        }
         // Put some feedback on the console if no pulse for 3 seconds
         if proc_n - proc_n0 > 3000 {
             let (dc, thresh) = hr.help();
+            let (lo, hi) = hr.crazy_window();
             msg.clear();
-            core::fmt::write(&mut msg, format_args!("Help: {} {}\n", dc, thresh)).unwrap();
+            core::fmt::write(
+                &mut msg,
+                format_args!(
+                    "Help: {} {} {} {} {} ch={} {:.2?}\n",
+                    dc,
+                    thresh,
+                    lo,
+                    hi,
+                    hr.reacquired(),
+                    best,
+                    scores
+                ),
+            )
+            .unwrap();
             _ = uart_ref.write(msg.as_bytes()).await;
             proc_n0 = proc_n;
         }
@@ -223,8 +592,96 @@ async fn main(spawner: Spawner) {
     let led3 = Output::new(p.PB14, Level::High, Speed::Low);
     let led3_ref = LED3_INST.init(led3);
 
-    let uart = UartTx::new(p.USART3, p.PD8, p.DMA1_CH1, Config::default()).unwrap();
-    let uart_ref = UART_INST.init(uart);
+    let uart = Uart::new(p.USART3, p.PD9, p.PD8, Irqs, p.DMA1_CH1, p.DMA1_CH2, Config::default()).unwrap();
+    let (uart_tx, uart_rx) = uart.split();
+    let uart_ref = UART_INST.init(uart_tx);
+    let uart_rx_ref = UARTRX_INST.init(uart_rx);
+
+    // Kick off the console task to listen for commands
+    _ = spawner.spawn(console::process(uart_rx_ref, &COMMAND_CHANNEL, &DISPLAY_SIGNAL));
+
+    // Set up the display, and place in static to pass into HR processing task
+    #[cfg(feature = "display-c5412")]
+    let display = {
+        use embassy_stm32::gpio::Pin;
+        let c5412pins = c5412::C5412Pins::new(
+            // Cathodes of each digit, left to right
+            [
+                [
+                    p.PD7.degrade(),
+                    p.PD6.degrade(),
+                    p.PD5.degrade(),
+                    p.PD4.degrade(),
+                    p.PD3.degrade(),
+                    p.PE2.degrade(),
+                    p.PF2.degrade(),
+                    p.PF1.degrade(),
+                ],
+                [
+                    p.PE4.degrade(),
+                    p.PE5.degrade(),
+                    p.PE6.degrade(),
+                    p.PE3.degrade(),
+                    p.PF8.degrade(),
+                    p.PF7.degrade(),
+                    p.PF9.degrade(),
+                    p.PG1.degrade(),
+                ],
+            ],
+            // Segments a..n
+            [
+                p.PC0.degrade(),
+                p.PB1.degrade(),
+                p.PD1.degrade(),
+                p.PD0.degrade(),
+                p.PG0.degrade(),
+                p.PF10.degrade(),
+                p.PE7.degrade(),
+                p.PE8.degrade(),
+                p.PE10.degrade(),
+                p.PF0.degrade(),
+                p.PE12.degrade(),
+                p.PE14.degrade(),
+                p.PE15.degrade(),
+                p.PA3.degrade(),
+            ],
+        )
+        .expect("C5412 pin map");
+        defmt::info!("C5412 on GPIO ports {}", c5412pins.map().port_names().as_str());
+        let c5412pins_ref = C5412PINS_INST.init(c5412pins);
+
+        // Kick off the display task
+        _ = spawner.spawn(c5412::process(c5412pins_ref, p.TIM7, &DISP_FRAMES_SIGNAL));
+        c5412::C5412Display::new(&DISP_FRAMES_SIGNAL, DISP_SCROLL_MS)
+    };
+    #[cfg(feature = "display-hd44780")]
+    let display = hd44780::Hd44780::new(
+        Output::new(p.PF12, Level::Low, Speed::Low).degrade(), // RS
+        Output::new(p.PF13, Level::Low, Speed::Low).degrade(), // E
+        [
+            Output::new(p.PF14, Level::Low, Speed::Low).degrade(), // D4
+            Output::new(p.PF15, Level::Low, Speed::Low).degrade(), // D5
+            Output::new(p.PG9, Level::Low, Speed::Low).degrade(),  // D6
+            Output::new(p.PG14, Level::Low, Speed::Low).degrade(), // D7
+        ],
+    );
+    #[cfg(feature = "display-ssd1306")]
+    let display = {
+        let i2c = embassy_stm32::i2c::I2c::new(
+            p.I2C1,
+            p.PB8,
+            p.PB9,
+            I2cIrqs,
+            embassy_stm32::dma::NoDma,
+            embassy_stm32::dma::NoDma,
+            embassy_stm32::time::Hertz(400_000),
+            Default::default(),
+        );
+        ssd1306::Ssd1306::new(I2C_INST.init(i2c))
+    };
+    #[cfg(feature = "display-mock")]
+    let display = display::Mock::new();
+    let display_ref = DISPLAY_INST.init(display);
 
     // Kick off the HR processing task
     _ = spawner.spawn(process_hr(
@@ -232,41 +689,11 @@ async fn main(spawner: Spawner) {
         led1_ref,
         led3_ref,
         button1_ref,
-        &DISP_VALUE_ATOMIC,
+        display_ref,
+        &DISPLAY_SIGNAL,
+        &COMMAND_CHANNEL,
     ));
 
-    // Set up the display, and place in static to pass into another task
-    let c5412pins = c5412::C5412Pins {
-        p11: Output::new(p.PD7, Level::High, Speed::Low).degrade(),
-        p12: Output::new(p.PD6, Level::High, Speed::Low).degrade(),
-        p13: Output::new(p.PD5, Level::High, Speed::Low).degrade(),
-        p14: Output::new(p.PD4, Level::High, Speed::Low).degrade(),
-        p15: Output::new(p.PD3, Level::High, Speed::Low).degrade(),
-        p16: Output::new(p.PE2, Level::High, Speed::Low).degrade(),
-        p17: Output::new(p.PF2, Level::High, Speed::Low).degrade(),
-        p18: Output::new(p.PF1, Level::High, Speed::Low).degrade(),
-        p21: Output::new(p.PE4, Level::High, Speed::Low).degrade(),
-        p22: Output::new(p.PE5, Level::High, Speed::Low).degrade(),
-        p23: Output::new(p.PE6, Level::High, Speed::Low).degrade(),
-        p24: Output::new(p.PE3, Level::High, Speed::Low).degrade(),
-        p25: Output::new(p.PF8, Level::High, Speed::Low).degrade(),
-        p26: Output::new(p.PF7, Level::High, Speed::Low).degrade(),
-        p27: Output::new(p.PF9, Level::High, Speed::Low).degrade(),
-        p28: Output::new(p.PG1, Level::High, Speed::Low).degrade(),
-        sah: Output::new(p.PC0, Level::High, Speed::Low).degrade(),
-        sbh: Output::new(p.PB1, Level::High, Speed::Low).degrade(),
-        sch: Output::new(p.PD1, Level::High, Speed::Low).degrade(),
-        sdh: Output::new(p.PD0, Level::High, Speed::Low).degrade(),
-        seh: Output::new(p.PG0, Level::High, Speed::Low).degrade(),
-        sfh: Output::new(p.PF10, Level::High, Speed::Low).degrade(),
-        sjh: Output::new(p.PF0, Level::High, Speed::Low).degrade(),
-        snh: Output::new(p.PA3, Level::High, Speed::Low).degrade(),
-    };
-    let c5412pins_ref = C5412PINS_INST.init(c5412pins);
-
-    // Kick off the display task
-    _ = spawner.spawn(c5412::process(c5412pins_ref, &DISP_VALUE_ATOMIC));
-
     //
     // Setup the ADC. This is a bit fancy right now!
     //
@@ -298,7 +725,9 @@ async fn main(spawner: Spawner) {
         now += 1; // Sample at 1kHz -- Using "tick-hz-1_000_000" feature of embassy-time
         ADC_N_ATOMIC.store(now as u32, Ordering::Relaxed);
         Timer::at(Instant::from_millis(now)).await;
-        let sample = adc.read(&mut p.PA0) as u32;
-        SAMPLE_CHANNEL.try_send(sample).expect("adc sample channel overrun");
+        // Sensor 0 on PA0 (ADC1 INP16), sensor 1 on PF11 (ADC1 INP2)
+        // Each oversampled read takes well under half the 1ms sample period
+        let frame: SampleFrame = [adc.read(&mut p.PA0) as u32, adc.read(&mut p.PF11) as u32];
+        SAMPLE_CHANNEL.try_send(frame).expect("adc sample channel overrun");
     }
 }
@@ -0,0 +1,138 @@
+// multi: Several synchronized sensor channels
+//
+// Each channel (eg. two fingers, or finger and earlobe) gets its own Hr.  A
+// combiner scores the channels by how confidently their beats are being
+// tracked, and picks the best one to report, with some hysteresis so it
+// doesn't flap between two similar channels.
+//
+// With two or more channels, the time between matching peaks on channel 0
+// and channel 1 is the pulse transit time (PTT) between the two sites.
+// Positive means the pulse reaches channel 1 later.
+
+use libm::sqrt;
+
+use crate::hr_alg3::Hr;
+
+const SCORE_ALPHA: f64 = 1.0 / 4.0; // Per beat smoothing of channel score
+const SCORE_DECAY: f64 = 1.0 / 1000.0; // Per sample decay while a channel has no lock
+const SWITCH_MARGIN: f64 = 0.1; // Score advantage needed to switch best channel
+const MAX_PTT: usize = 300; // Peaks further apart than this don't match
+const PTT_ALPHA: f64 = 1.0 / 8.0;
+
+pub struct Sensors<const N: usize> {
+    hrs: [Hr; N],
+    scores: [f64; N],
+    best: usize,
+    peak_n: [usize; N], // Last peak on each channel
+    paired: [bool; N],  // Last peak has already been used for PTT
+    ptt: Option<f64>,   // Smoothed PTT, ms
+}
+
+impl<const N: usize> Sensors<N> {
+    pub fn new() -> Self {
+        Self {
+            hrs: core::array::from_fn(|_| Hr::new()),
+            scores: [0.0; N],
+            best: 0,
+            peak_n: [0; N],
+            paired: [true; N],
+            ptt: None,
+        }
+    }
+    // Process one sample from each channel
+    // Return each channel's Hr::tick result
+    pub fn tick(&mut self, lp: bool, frame: &[u32; N]) -> [(usize, u32, u8, u8); N] {
+        let mut out = [(0, 0, 0, 0); N];
+        for ch in 0..N {
+            out[ch] = self.hrs[ch].tick(lp, frame[ch]);
+            if out[ch].3 != 0 {
+                self.beat(ch);
+            } else if self.hrs[ch].tracked().0 == 0.0 || self.hrs[ch].clipping() {
+                self.scores[ch] -= self.scores[ch] * SCORE_DECAY;
+            }
+        }
+        self.choose();
+        out
+    }
+    fn beat(&mut self, ch: usize) {
+        // Score is higher the tighter the tracked HR, 0 if it is not tracked
+        let (rate, var) = self.hrs[ch].tracked();
+        let s = if rate > 0.0 { 1.0 / (1.0 + sqrt(var)) } else { 0.0 };
+        self.scores[ch] += (s - self.scores[ch]) * SCORE_ALPHA;
+
+        let (peak_n, _, _) = self.hrs[ch].last_beat();
+        self.peak_n[ch] = peak_n;
+        self.paired[ch] = false;
+        if N >= 2 && ch <= 1 {
+            let other = 1 - ch;
+            let d = peak_n.abs_diff(self.peak_n[other]);
+            if !self.paired[other] && d <= MAX_PTT {
+                let ptt = self.peak_n[1] as f64 - self.peak_n[0] as f64;
+                self.ptt = Some(match self.ptt {
+                    Some(p) => p + (ptt - p) * PTT_ALPHA,
+                    None => ptt,
+                });
+                self.paired[0] = true;
+                self.paired[1] = true;
+            }
+        }
+    }
+    fn choose(&mut self) {
+        for ch in 0..N {
+            if self.scores[ch] > self.scores[self.best] + SWITCH_MARGIN {
+                self.best = ch;
+            }
+        }
+    }
+    // Return the channel currently judged best
+    pub fn best(&self) -> usize {
+        self.best
+    }
+    // Return each channel's quality score, 0..1
+    pub fn scores(&self) -> &[f64; N] {
+        &self.scores
+    }
+    // Return smoothed pulse transit time from channel 0 to 1 in ms, if measured
+    pub fn ptt(&self) -> Option<f64> {
+        self.ptt
+    }
+    // Return one channel's Hr, eg. the best one to report
+    pub fn hr_mut(&mut self, ch: usize) -> &mut Hr {
+        &mut self.hrs[ch]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::exp;
+
+    // Synthetic PPG at 75 BPM, with the peaks delay_ms later than channel 0's
+    fn pulse(n: usize, delay_ms: usize) -> u32 {
+        let t = ((n + 800 - delay_ms) % 800) as f64;
+        (32768.0 + 600.0 * exp(-((t - 150.0) / 60.0) * ((t - 150.0) / 60.0))) as u32
+    }
+
+    #[test]
+    fn ptt() {
+        let mut sensors = Sensors::<2>::new();
+        for n in 0..20000 {
+            sensors.tick(false, &[pulse(n, 0), pulse(n, 40)]);
+        }
+        assert!((sensors.ptt().unwrap() - 40.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn best() {
+        // Nothing on channel 0, so channel 1 is picked once it locks
+        let mut sensors = Sensors::<2>::new();
+        for n in 0..20000 {
+            sensors.tick(false, &[32768, pulse(n, 0)]);
+        }
+        assert_eq!(sensors.best(), 1);
+        assert_eq!(sensors.scores()[0], 0.0);
+        assert!(sensors.scores()[1] > SWITCH_MARGIN);
+        assert!(sensors.ptt().is_none());
+        assert!(sensors.hr_mut(1).tracked().0 > 74.0);
+    }
+}
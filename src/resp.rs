@@ -0,0 +1,199 @@
+// resp: Respiratory rate estimation from PPG modulation
+//
+// Breathing modulates the pulse signal three ways, all of which we already
+// have for each beat:
+//   * Baseline (respiratory induced intensity variation): dc_ema at the beat
+//   * Amplitude (respiratory induced amplitude variation): peak to foot
+//   * Rate (respiratory sinus arrhythmia): inter-beat interval
+// Each series is sampled at the beats, so it is resampled onto an even grid,
+// detrended, and the strongest frequency in the breathing band is found with
+// Goertzel filters.  The three estimates are fused: if they agree, their mean
+// is reported as good; if not, their median is reported as poor quality.
+//
+// Estimates are made every UPDATE_MS, over the last WINDOW_MS of beats.
+
+use libm::{cos, fabs};
+use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
+
+const BEATS: usize = 128; // Beats kept, enough for WINDOW_MS at 240 BPM
+const WINDOW_MS: usize = 32000;
+const MIN_SPAN_MS: usize = 20000; // Beats must cover this much of the window
+const UPDATE_MS: usize = 5000;
+const GRID: usize = 64; // Resampled points over WINDOW_MS, 2Hz
+const GRID_MS: f64 = (WINDOW_MS / GRID) as f64;
+const MIN_BPM: f64 = 6.0; // Breathing band, breaths per minute
+const MAX_BPM: f64 = 42.0;
+const STEP_BPM: f64 = 0.5;
+const AGREE_BPM: f64 = 4.0; // Estimates within this of each other are good
+
+#[derive(Clone, Copy)]
+struct Beat {
+    n: usize,
+    values: [f64; 3], // Baseline, amplitude, IBI
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct RespRate {
+    pub bpm: f64,        // Fused breaths per minute
+    pub rates: [f64; 3], // Baseline, amplitude and IBI estimates
+    pub good: bool,      // True if the three estimates agreed
+}
+
+pub struct Resp {
+    beats: ConstGenericRingBuffer<Beat, BEATS>,
+    last_update_n: usize,
+}
+
+impl Resp {
+    pub fn new() -> Resp {
+        Resp {
+            beats: ConstGenericRingBuffer::<Beat, BEATS>::new(),
+            last_update_n: 0,
+        }
+    }
+    // Forget beat history, eg. after the signal was lost
+    pub fn restart(&mut self) {
+        self.beats.clear();
+    }
+    // Add one beat at sample number n
+    // Return a new estimate every UPDATE_MS, once enough beats are collected
+    pub fn beat(&mut self, n: usize, baseline: f64, amplitude: f64, ibi: usize) -> Option<RespRate> {
+        self.beats.push(Beat {
+            n,
+            values: [baseline, amplitude, ibi as f64],
+        });
+        if n < self.last_update_n + UPDATE_MS {
+            return None;
+        }
+        let first_n = self.beats.iter().map(|b| b.n).find(|bn| n - bn <= WINDOW_MS)?;
+        if n - first_n < MIN_SPAN_MS {
+            return None;
+        }
+        self.last_update_n = n;
+
+        let mut rates = [0f64; 3];
+        for (k, rate) in rates.iter_mut().enumerate() {
+            let grid = self.resample(n, k);
+            *rate = strongest(&grid);
+        }
+        let mut sorted = rates;
+        sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+        let good = sorted[2] - sorted[0] <= AGREE_BPM;
+        let bpm = if good {
+            (rates[0] + rates[1] + rates[2]) / 3.0
+        } else {
+            sorted[1]
+        };
+        Some(RespRate { bpm, rates, good })
+    }
+    // Linearly interpolate series k onto GRID points ending at sample n, then
+    //   remove the linear trend
+    fn resample(&self, n: usize, k: usize) -> [f64; GRID] {
+        let mut grid = [0f64; GRID];
+        let len = self.beats.len();
+        let mut ix = 0; // First beat after t
+        for (i, g) in grid.iter_mut().enumerate() {
+            let t = n as f64 - (GRID - 1 - i) as f64 * GRID_MS;
+            while ix < len && self.beats[ix].n as f64 <= t {
+                ix += 1;
+            }
+            *g = if ix == 0 {
+                self.beats[0].values[k]
+            } else if ix == len {
+                self.beats[len - 1].values[k]
+            } else {
+                let (a, b) = (self.beats[ix - 1], self.beats[ix]);
+                let f = (t - a.n as f64) / (b.n - a.n) as f64;
+                a.values[k] + f * (b.values[k] - a.values[k])
+            };
+        }
+        // Least squares line through (i, grid[i])
+        let m = GRID as f64;
+        let mean_i = (m - 1.0) / 2.0;
+        let mean_g = grid.iter().sum::<f64>() / m;
+        let mut sxy = 0.0;
+        let mut sxx = 0.0;
+        for (i, g) in grid.iter().enumerate() {
+            sxy += (i as f64 - mean_i) * (g - mean_g);
+            sxx += (i as f64 - mean_i) * (i as f64 - mean_i);
+        }
+        let slope = sxy / sxx;
+        for (i, g) in grid.iter_mut().enumerate() {
+            *g -= mean_g + slope * (i as f64 - mean_i);
+        }
+        grid
+    }
+}
+
+// Return the frequency in the breathing band with the most power, in BPM
+fn strongest(grid: &[f64; GRID]) -> f64 {
+    let mut best_bpm = 0.0;
+    let mut best_power = 0.0;
+    let mut bpm = MIN_BPM;
+    while bpm <= MAX_BPM {
+        // Goertzel filter at bpm
+        let w = 2.0 * core::f64::consts::PI * (bpm / 60.0) * (GRID_MS / 1000.0);
+        let coeff = 2.0 * cos(w);
+        let (mut s1, mut s2) = (0f64, 0f64);
+        for x in grid.iter() {
+            let s0 = x + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        let power = fabs(s1 * s1 + s2 * s2 - coeff * s1 * s2);
+        if power > best_power {
+            best_power = power;
+            best_bpm = bpm;
+        }
+        bpm += STEP_BPM;
+    }
+    best_bpm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::sin;
+
+    // Beats at about 75 BPM, with breathing at bpm modulating the baseline,
+    //   amplitude and IBI
+    // Return the estimates from ms of them
+    fn estimates(bpm: f64, ms: usize) -> heapless::Vec<RespRate, 16> {
+        let mut resp = Resp::new();
+        let mut rates = heapless::Vec::new();
+        let mut n = 1000;
+        while n < ms {
+            let breath = sin(2.0 * core::f64::consts::PI * bpm / 60000.0 * n as f64);
+            let ibi = (800.0 + 40.0 * breath) as usize;
+            n += ibi;
+            if let Some(rate) = resp.beat(n, 32768.0 + 50.0 * breath, 600.0 - 60.0 * breath, ibi) {
+                _ = rates.push(rate);
+            }
+        }
+        rates
+    }
+
+    #[test]
+    fn breathing_rate() {
+        for bpm in [8.0, 15.0, 30.0] {
+            let rates = estimates(bpm, 60000);
+            // Not until the beats span MIN_SPAN_MS, then every UPDATE_MS
+            assert!(rates.len() >= (60000 - 1000 - MIN_SPAN_MS) / UPDATE_MS);
+            for rate in rates.iter() {
+                assert!(rate.good);
+                assert!((rate.bpm - bpm).abs() < 1.0, "{} for {}", rate.bpm, bpm);
+                assert!(rate.rates.iter().all(|r| (*r - bpm).abs() < 1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn restart() {
+        let mut resp = Resp::new();
+        let updates = (1..40).filter_map(|k| resp.beat(k * 800, 32768.0, 600.0, 800)).count();
+        assert!(updates > 0);
+        // After a restart, it takes another MIN_SPAN_MS of beats
+        resp.restart();
+        assert!((40..40 + MIN_SPAN_MS / 800).all(|k| resp.beat(k * 800, 32768.0, 600.0, 800).is_none()));
+    }
+}
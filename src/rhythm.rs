@@ -0,0 +1,288 @@
+// rhythm: Rhythm screening from inter-beat intervals (IBI)
+//
+// Not a diagnosis!  This looks at the stream of peak to peak intervals coming
+// out of hr_alg3 and flags patterns worth telling the user about:
+//
+// * Premature beat: an IBI well short of the recent average, followed by a
+//   compensating long one (short-long pattern)
+// * Pause: an IBI much longer than the recent average
+// * Bradycardia / tachycardia: the average HR over a window of beats stays
+//   below / above a limit
+// * Irregular rhythm: over a window of beats, successive differences are both
+//   large (normalized RMSSD) and spread out (normalized Shannon entropy), ie.
+//   "irregularly irregular" rather than a steady or slowly changing rhythm
+//
+// Sustained conditions are reported once when they start and once when they
+// end, so events can simply be logged as they come out.
+
+use heapless::Vec;
+use libm::{log, sqrt};
+use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
+
+const WINDOW: usize = 32; // IBIs considered for sustained conditions
+const MIN_WINDOW: usize = 16; // IBIs needed before judging sustained conditions
+const PREMATURE_RATIO: f64 = 0.80; // Short IBI relative to average
+const COMPENSATE_RATIO: f64 = 1.10; // Following long IBI relative to average
+const PAUSE_RATIO: f64 = 1.80; // Pause IBI relative to average
+const BRADY_BPM: f64 = 50.0;
+const TACHY_BPM: f64 = 120.0;
+const IRREGULAR_NRMSSD: f64 = 0.10; // RMSSD / mean IBI
+const IRREGULAR_ENTROPY: f64 = 0.60; // Shannon entropy / log(ENTROPY_BINS)
+const ENTROPY_BINS: usize = 16;
+const ENTROPY_SPAN: f64 = 0.5; // Normalized successive differences binned over +/- this
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RhythmKind {
+    Premature,
+    Pause,
+    BradyStart,
+    BradyEnd,
+    TachyStart,
+    TachyEnd,
+    IrregularStart,
+    IrregularEnd,
+}
+
+impl RhythmKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RhythmKind::Premature => "premature",
+            RhythmKind::Pause => "pause",
+            RhythmKind::BradyStart => "brady-start",
+            RhythmKind::BradyEnd => "brady-end",
+            RhythmKind::TachyStart => "tachy-start",
+            RhythmKind::TachyEnd => "tachy-end",
+            RhythmKind::IrregularStart => "irregular-start",
+            RhythmKind::IrregularEnd => "irregular-end",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RhythmEvent {
+    pub n: usize, // Sample number (ms since boot) of the beat that triggered it
+    pub kind: RhythmKind,
+}
+
+pub type RhythmEvents = Vec<RhythmEvent, 4>;
+
+pub struct Rhythm {
+    ibis: ConstGenericRingBuffer<f64, WINDOW>,
+    prev_ibi: f64, // IBI before the most recent one, 0 if none
+    prev_n: usize, // Sample number of the beat ending prev_ibi
+    brady: bool,
+    tachy: bool,
+    irregular: bool,
+}
+
+impl Rhythm {
+    pub fn new() -> Rhythm {
+        Rhythm {
+            ibis: ConstGenericRingBuffer::<f64, WINDOW>::new(),
+            prev_ibi: 0.0,
+            prev_n: 0,
+            brady: false,
+            tachy: false,
+            irregular: false,
+        }
+    }
+    // Forget beat history, eg. after the signal was lost
+    //   Sustained conditions in progress are left as they are, and will end
+    //   (or not) once the window fills again
+    pub fn restart(&mut self) {
+        self.ibis.clear();
+        self.prev_ibi = 0.0;
+    }
+    // Process one beat at sample n ending an interval of ibi samples
+    // Return any events this beat caused
+    pub fn beat(&mut self, n: usize, ibi: usize) -> RhythmEvents {
+        let mut events = RhythmEvents::new();
+        let ibi = ibi as f64;
+        let mean = self.mean();
+
+        if self.ibis.len() >= MIN_WINDOW / 2 {
+            // Short-long: the short one was premature
+            if self.prev_ibi > 0.0 && self.prev_ibi < PREMATURE_RATIO * mean && ibi > COMPENSATE_RATIO * mean {
+                _ = events.push(RhythmEvent {
+                    n: self.prev_n,
+                    kind: RhythmKind::Premature,
+                });
+            }
+            if ibi > PAUSE_RATIO * mean {
+                _ = events.push(RhythmEvent {
+                    n,
+                    kind: RhythmKind::Pause,
+                });
+                // Don't let a pause skew the average
+                self.prev_ibi = 0.0;
+                return events;
+            }
+        }
+        self.ibis.push(ibi);
+        self.prev_ibi = ibi;
+        self.prev_n = n;
+
+        if self.ibis.len() >= MIN_WINDOW {
+            let hr = 60000.0 / self.mean();
+            Self::edge(
+                &mut events,
+                n,
+                &mut self.brady,
+                hr < BRADY_BPM,
+                RhythmKind::BradyStart,
+                RhythmKind::BradyEnd,
+            );
+            Self::edge(
+                &mut events,
+                n,
+                &mut self.tachy,
+                hr > TACHY_BPM,
+                RhythmKind::TachyStart,
+                RhythmKind::TachyEnd,
+            );
+            let (nrmssd, entropy) = self.irregularity();
+            let irregular = nrmssd > IRREGULAR_NRMSSD && entropy > IRREGULAR_ENTROPY;
+            Self::edge(
+                &mut events,
+                n,
+                &mut self.irregular,
+                irregular,
+                RhythmKind::IrregularStart,
+                RhythmKind::IrregularEnd,
+            );
+        }
+        events
+    }
+    fn edge(events: &mut RhythmEvents, n: usize, flag: &mut bool, now: bool, start: RhythmKind, end: RhythmKind) {
+        if now != *flag {
+            *flag = now;
+            _ = events.push(RhythmEvent {
+                n,
+                kind: if now { start } else { end },
+            });
+        }
+    }
+    fn mean(&self) -> f64 {
+        if self.ibis.is_empty() {
+            return 0.0;
+        }
+        self.ibis.iter().sum::<f64>() / self.ibis.len() as f64
+    }
+    // Return (RMSSD / mean IBI, entropy of successive differences / max entropy)
+    //   for the IBIs in the window
+    pub fn irregularity(&self) -> (f64, f64) {
+        let len = self.ibis.len();
+        if len < 3 {
+            return (0.0, 0.0);
+        }
+        let mean = self.mean();
+        let mut bins = [0u32; ENTROPY_BINS];
+        let mut ssd = 0.0;
+        for (a, b) in self.ibis.iter().zip(self.ibis.iter().skip(1)) {
+            let d = (b - a) / mean;
+            ssd += d * d;
+            let ix = ((d + ENTROPY_SPAN) / (2.0 * ENTROPY_SPAN) * ENTROPY_BINS as f64) as isize;
+            bins[ix.clamp(0, ENTROPY_BINS as isize - 1) as usize] += 1;
+        }
+        let count = (len - 1) as f64;
+        let mut entropy = 0.0;
+        for b in bins.iter().filter(|b| **b > 0) {
+            let p = *b as f64 / count;
+            entropy -= p * log(p);
+        }
+        (sqrt(ssd / count), entropy / log(ENTROPY_BINS as f64))
+    }
+    // Return whether brady, tachy and irregular conditions are in progress
+    pub fn status(&self) -> (bool, bool, bool) {
+        (self.brady, self.tachy, self.irregular)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IBI: usize = 800; // 75 BPM
+
+    // Feed IBIs to a fresh Rhythm, beats at their cumulative sample number
+    // Return every event
+    fn screen(ibis: &[usize]) -> Vec<RhythmEvent, 16> {
+        let mut rhythm = Rhythm::new();
+        let mut events = Vec::new();
+        let mut n = 0;
+        for ibi in ibis.iter() {
+            n += ibi;
+            for e in rhythm.beat(n, *ibi).iter() {
+                events.push(*e).unwrap();
+            }
+        }
+        events
+    }
+
+    fn kinds(events: &[RhythmEvent]) -> Vec<RhythmKind, 16> {
+        events.iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn steady() {
+        assert!(screen(&[IBI; 40]).is_empty());
+    }
+
+    #[test]
+    fn premature() {
+        // Short-long, reported at the short beat
+        let mut ibis = [IBI; 20];
+        ibis[10] = 500;
+        ibis[11] = 1100;
+        let events = screen(&ibis);
+        assert_eq!(kinds(&events).as_slice(), &[RhythmKind::Premature]);
+        assert_eq!(events[0].n, 10 * IBI + 500);
+        // Short without the long is just variation
+        ibis[11] = IBI;
+        assert!(screen(&ibis).is_empty());
+    }
+
+    #[test]
+    fn pause() {
+        let mut ibis = [IBI; 40];
+        ibis[10] = 2000;
+        let events = screen(&ibis);
+        assert_eq!(kinds(&events).as_slice(), &[RhythmKind::Pause]);
+        assert_eq!(events[0].n, 10 * IBI + 2000);
+        // The pause is left out of the average, so a shorter one right after
+        //   is still a pause
+        ibis[11] = 1500;
+        assert_eq!(
+            kinds(&screen(&ibis)).as_slice(),
+            &[RhythmKind::Pause, RhythmKind::Pause]
+        );
+        // A premature beat before a pause is reported with it, but not again
+        //   on the next beat, though that's long too
+        ibis[9] = 500;
+        ibis[11] = 1100;
+        assert_eq!(
+            kinds(&screen(&ibis)).as_slice(),
+            &[RhythmKind::Premature, RhythmKind::Pause]
+        );
+    }
+
+    #[test]
+    fn irregular() {
+        // Uniform spread of +/- 30%, as in atrial fibrillation
+        let mut ibis = [IBI; 80];
+        let mut x: u32 = 1;
+        for ibi in ibis[..60].iter_mut() {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            *ibi = IBI * 7 / 10 + (x >> 16) as usize % (IBI * 6 / 10);
+        }
+        let events = screen(&ibis);
+        assert!(kinds(&events).contains(&RhythmKind::IrregularStart));
+        assert_eq!(events.last().unwrap().kind, RhythmKind::IrregularEnd);
+        // Slowly changing is not irregular, however much it changes
+        let mut ibis = [IBI; 60];
+        for (i, ibi) in ibis.iter_mut().enumerate() {
+            *ibi = 600 + 10 * i;
+        }
+        assert!(!kinds(&screen(&ibis)).contains(&RhythmKind::IrregularStart));
+    }
+}
@@ -0,0 +1,189 @@
+// session: Session summary statistics
+//
+// A session starts once the tracker has held a lock for START_S seconds, and
+// ends when there has been no valid HR for END_S seconds, ie. the sensor was
+// taken off.  While it runs, the HR is sampled at the end of each second,
+// like trend's points:
+//   * Valid seconds go into a Stats accumulator for min/mean/max, and into
+//     time-in-zone
+//   * Resting HR is the lowest average over REST_S valid seconds in a row
+//   * The valid fraction is valid seconds over the session duration
+// The trailing seconds without HR that ended the session are not counted.
+
+use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
+use stats::Stats;
+
+use crate::zones::ZONES;
+
+const SECOND: usize = 1000; // Samples
+const START_S: u32 = 5; // Valid seconds in a row to start a session
+const END_S: u32 = 15; // Invalid seconds in a row to end a session
+const REST_S: usize = 60; // Resting HR averaging window
+
+#[derive(Clone, Copy)]
+pub struct Summary {
+    pub duration_s: u32,
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub resting: f64, // 0 if never REST_S valid seconds in a row
+    pub zone_s: [u32; ZONES],
+    pub valid: f64, // Fraction of the session with a valid HR
+}
+
+pub struct Session {
+    active: bool,
+    run: u32,     // Valid seconds in a row before starting, invalid ones while active
+    seconds: u32, // Session duration so far, including a trailing invalid run
+    valid_s: u32,
+    hr: Stats,
+    zone_s: [u32; ZONES],
+    minute: ConstGenericRingBuffer<f64, REST_S>, // Latest valid run of HR
+    resting: f64,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            active: false,
+            run: 0,
+            seconds: 0,
+            valid_s: 0,
+            hr: Stats::new(),
+            zone_s: [0; ZONES],
+            minute: ConstGenericRingBuffer::<f64, REST_S>::new(),
+            resting: 0.0,
+        }
+    }
+    pub fn active(&self) -> bool {
+        self.active
+    }
+    // Feed sample number n, with the tracked HR bpm (0 if none), whether the
+    //   signal is valid, and the current training zone
+    // Return the summary when a session ends
+    pub fn tick(&mut self, n: usize, bpm: f64, valid: bool, zone: u8) -> Option<Summary> {
+        if (n + 1) % SECOND != 0 {
+            return None;
+        }
+        let valid = valid && bpm > 0.0;
+        // While waiting to start, run counts valid seconds; once started it
+        //   counts invalid ones
+        if !self.active {
+            self.run = if valid { self.run + 1 } else { 0 };
+            if self.run < START_S {
+                return None;
+            }
+            self.start();
+        }
+        self.seconds += 1;
+        if !valid {
+            self.minute.clear();
+            self.run += 1;
+            if self.run < END_S {
+                return None;
+            }
+            let summary = self.summary();
+            self.active = false;
+            self.run = 0;
+            return Some(summary);
+        }
+        self.run = 0;
+        self.valid_s += 1;
+        self.hr.add(bpm);
+        self.zone_s[zone as usize] += 1;
+        self.minute.push(bpm);
+        if self.minute.is_full() {
+            let mean = self.minute.iter().sum::<f64>() / REST_S as f64;
+            if self.resting == 0.0 || mean < self.resting {
+                self.resting = mean;
+            }
+        }
+        None
+    }
+    fn start(&mut self) {
+        self.active = true;
+        self.run = 0;
+        self.seconds = 0;
+        self.valid_s = 0;
+        self.hr.reset();
+        self.zone_s = [0; ZONES];
+        self.minute.clear();
+        self.resting = 0.0;
+    }
+    // Summary of the session so far, less any trailing invalid seconds
+    pub fn summary(&self) -> Summary {
+        let duration_s = self.seconds - self.run;
+        Summary {
+            duration_s,
+            min: self.hr.min(),
+            mean: self.hr.mean(),
+            max: self.hr.max(),
+            resting: self.resting,
+            zone_s: self.zone_s,
+            valid: if duration_s > 0 {
+                self.valid_s as f64 / duration_s as f64
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run a session through seconds of (bpm, zone), 0 bpm meaning no HR
+    // Return the summary when it ended, if it did, and the second it started
+    fn run(f: impl Fn(usize) -> (f64, u8), seconds: usize) -> (Option<Summary>, Option<usize>) {
+        let mut session = Session::new();
+        let mut summary = None;
+        let mut started = None;
+        for n in 0..seconds * SECOND {
+            let (bpm, zone) = f(n / SECOND);
+            if let Some(s) = session.tick(n, bpm, bpm > 0.0, zone) {
+                summary = Some(s);
+            }
+            if session.active() && started.is_none() {
+                started = Some(n / SECOND);
+            }
+        }
+        (summary, started)
+    }
+
+    #[test]
+    fn summary() {
+        // No HR, then 70 in zone 1, a short gap, 100 in zone 2, and off
+        let (summary, started) = run(
+            |s| match s {
+                10..=129 => (70.0, 1),
+                140..=199 => (100.0, 2),
+                _ => (0.0, 0),
+            },
+            300,
+        );
+        // Starts on the START_S'th valid second, which counts
+        assert_eq!(started, Some(14));
+        let summary = summary.unwrap();
+        // Ends at 200s, not counting the END_S seconds without HR after
+        assert_eq!(summary.duration_s, 186);
+        assert_eq!(summary.min, 70.0);
+        assert_eq!(summary.max, 100.0);
+        assert!((summary.mean - (116.0 * 70.0 + 60.0 * 100.0) / 176.0).abs() < 1e-9);
+        assert_eq!(summary.resting, 70.0);
+        assert_eq!(summary.zone_s[1], 116);
+        assert_eq!(summary.zone_s[2], 60);
+        assert!((summary.valid - 176.0 / 186.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn no_session() {
+        // Too short a run of HR to start, then too short a session to rest
+        let (summary, started) = run(|s| if s % 10 < 4 { (70.0, 0) } else { (0.0, 0) }, 100);
+        assert!(summary.is_none() && started.is_none());
+        let (summary, _) = run(|s| if s < 30 { (70.0, 0) } else { (0.0, 0) }, 100);
+        let summary = summary.unwrap();
+        assert_eq!(summary.duration_s, 26);
+        assert_eq!(summary.resting, 0.0);
+    }
+}
@@ -0,0 +1,203 @@
+// spo2: Two wavelength SpO2 estimation
+//
+// Processing side for a red/IR sensor pair.  The two channels are sampled
+// together; beats are found on the IR channel (usually the stronger one) with
+// the same hr_alg3 algorithm as the single sensor, and each beat segments both
+// channels.  For each beat the pulsatile (AC, peak to peak) and steady (DC,
+// mean) parts of each channel give the ratio of ratios
+//
+//     R = (AC_red / DC_red) / (AC_ir / DC_ir)
+//
+// which a calibration curve maps to SpO2.  The curve depends on the LEDs and
+// photodiode actually used, so it is configurable.
+//
+// Beats are gated before they count: the beat must be clean and accepted by
+// the tracker, both channels must be perfused enough to measure, and R must be
+// plausible.  The reading is only flagged good once several gated beats agree.
+
+use crate::hr_alg3::Hr;
+use crate::hr_track::BeatFit;
+
+const MIN_PI: f64 = 0.002; // AC/DC below this is too weak to measure
+const MIN_R: f64 = 0.3;
+const MAX_R: f64 = 2.0;
+const R_ALPHA: f64 = 1.0 / 4.0; // Smoothing of R across beats
+const AGREE: f64 = 0.1; // Beat R within this fraction of smoothed R agrees
+const GOOD_BEATS: u32 = 3; // Agreeing beats in a row for a good reading
+
+// SpO2 = a + b*R + c*R^2, in percent
+#[derive(Clone, Copy)]
+pub struct Calibration {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+impl Calibration {
+    // Commonly quoted empirical linear fit, good enough to start with
+    pub const DEFAULT: Calibration = Calibration {
+        a: 110.0,
+        b: -25.0,
+        c: 0.0,
+    };
+    pub fn spo2(&self, r: f64) -> f64 {
+        (self.a + self.b * r + self.c * r * r).clamp(0.0, 100.0)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Spo2Reading {
+    pub r: f64,    // Smoothed ratio of ratios
+    pub spo2: f64, // Percent
+    pub good: bool,
+}
+
+// Running min/max/mean of one channel over a beat
+#[derive(Clone, Copy)]
+struct Segment {
+    min: u32,
+    max: u32,
+    sum: f64,
+    n: usize,
+}
+
+impl Segment {
+    const EMPTY: Segment = Segment {
+        min: u32::MAX,
+        max: 0,
+        sum: 0.0,
+        n: 0,
+    };
+    fn add(&mut self, x: u32) {
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.sum += x as f64;
+        self.n += 1;
+    }
+    // Return AC/DC, or 0 if nothing collected
+    fn ratio(&self) -> f64 {
+        if self.n == 0 || self.sum == 0.0 {
+            return 0.0;
+        }
+        (self.max - self.min) as f64 / (self.sum / self.n as f64)
+    }
+}
+
+pub struct Spo2 {
+    hr: Hr,
+    lp: bool,
+    cal: Calibration,
+    red: Segment,
+    ir: Segment,
+    r: f64,     // Smoothed R, 0 until the first gated beat
+    agree: u32, // Gated beats in a row agreeing with r
+    reading: Spo2Reading,
+}
+
+impl Spo2 {
+    // Parameters:
+    //    lp: low pass the IR channel for beat detection, as for Hr::tick
+    //    cal: calibration curve for the sensor pair
+    pub fn new(lp: bool, cal: Calibration) -> Spo2 {
+        Spo2 {
+            hr: Hr::new(),
+            lp,
+            cal,
+            red: Segment::EMPTY,
+            ir: Segment::EMPTY,
+            r: 0.0,
+            agree: 0,
+            reading: Spo2Reading::default(),
+        }
+    }
+    // Process one pair of samples
+    // Return a new reading after each beat that passed the gates
+    pub fn tick(&mut self, red: u32, ir: u32) -> Option<Spo2Reading> {
+        self.red.add(red);
+        self.ir.add(ir);
+        let (_, _, _, hr_update) = self.hr.tick(self.lp, ir);
+        if hr_update == 0 {
+            return None;
+        }
+        // Segment ends at each beat
+        let (red_pi, ir_pi) = (self.red.ratio(), self.ir.ratio());
+        self.red = Segment::EMPTY;
+        self.ir = Segment::EMPTY;
+
+        let (_, _, clean) = self.hr.last_beat();
+        let accepted = matches!(self.hr.fit(), BeatFit::Accepted);
+        if !clean || !accepted || red_pi < MIN_PI || ir_pi < MIN_PI {
+            self.agree = 0;
+            return None;
+        }
+        let r = red_pi / ir_pi;
+        if !(MIN_R..=MAX_R).contains(&r) {
+            self.agree = 0;
+            return None;
+        }
+        if self.r == 0.0 {
+            self.r = r;
+        }
+        if (r - self.r).abs() <= AGREE * self.r {
+            self.agree += 1;
+        } else {
+            self.agree = 0;
+        }
+        self.r += (r - self.r) * R_ALPHA;
+        self.reading = Spo2Reading {
+            r: self.r,
+            spo2: self.cal.spo2(self.r),
+            good: self.agree >= GOOD_BEATS,
+        };
+        Some(self.reading)
+    }
+    // Return most recent reading
+    pub fn reading(&self) -> Spo2Reading {
+        self.reading
+    }
+    // Return the beat detector running on the IR channel
+    pub fn hr(&self) -> &Hr {
+        &self.hr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::exp;
+
+    // Pulse shape 0..1, 800ms period
+    fn pulse(n: usize) -> f64 {
+        let t = (n % 800) as f64;
+        exp(-((t - 150.0) / 60.0) * ((t - 150.0) / 60.0))
+    }
+
+    // Red and IR channels with the given ratio of ratios
+    fn run(r: f64, n_max: usize) -> Option<Spo2Reading> {
+        let mut spo2 = Spo2::new(false, Calibration::DEFAULT);
+        let mut reading = None;
+        for n in 0..n_max {
+            let ir = 32768.0 + 600.0 * pulse(n);
+            let red = 30000.0 + r * 600.0 * (30000.0 / 32768.0) * pulse(n);
+            reading = spo2.tick(red as u32, ir as u32).or(reading);
+        }
+        reading
+    }
+
+    #[test]
+    fn ratio_of_ratios() {
+        let reading = run(0.6, 20000).unwrap();
+        assert!(reading.good);
+        assert!((reading.r - 0.6).abs() < 0.01);
+        assert!((reading.spo2 - 95.0).abs() < 0.5);
+
+        let reading = run(1.0, 20000).unwrap();
+        assert!((reading.spo2 - 85.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn gates_weak_red() {
+        // Red channel with no pulse at all never gives a reading
+        assert!(run(0.0, 20000).is_none());
+    }
+}
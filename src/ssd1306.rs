@@ -0,0 +1,153 @@
+// ssd1306: 128x32 I2C OLED backend
+//
+// Text is drawn with the same 14-segment font as the LED display, scaled up
+// to 16x28 pixel characters, so up to 5 fit across.  Drawing goes into a
+// framebuffer that is sent over I2C (about 13ms at 400kHz, blocking) only when
+// what is shown changes.
+
+use heapless::String;
+
+use crate::display::{self, Display};
+use crate::glyph::{self, Glyph};
+
+const ADDR: u8 = 0x3c;
+const WIDTH: usize = 128;
+const PAGES: usize = 4; // 8 pixel rows each
+const CHAR_W: i32 = 16; // Segment box of each character
+const CHAR_H: i32 = 28;
+const CELL_W: i32 = 24; // Character spacing
+const CHARS: usize = 5;
+
+// Each segment a..n as a line between two points of the character box:
+//   0: top left, 1: top center, 2: top right,
+//   3: middle left, 4: center, 5: middle right,
+//   6: bottom left, 7: bottom center, 8: bottom right
+const SEGMENT_LINES: [(usize, usize); glyph::SEGMENTS] = [
+    (0, 2), // a
+    (2, 5), // b
+    (5, 8), // c
+    (6, 8), // d
+    (3, 6), // e
+    (0, 3), // f
+    (0, 4), // g
+    (1, 4), // h
+    (2, 4), // i
+    (3, 4), // j
+    (6, 4), // k
+    (4, 7), // l
+    (4, 8), // m
+    (4, 5), // n
+];
+
+#[rustfmt::skip]
+const INIT: [u8; 25] = [
+    0xae,       // Display off
+    0xd5, 0x80, // Clock divide
+    0xa8, 0x1f, // Multiplex: 32 rows
+    0xd3, 0x00, // No display offset
+    0x40,       // Start line 0
+    0x8d, 0x14, // Charge pump on
+    0x20, 0x00, // Horizontal addressing
+    0xa1, 0xc8, // Flip both ways so (0, 0) is top left
+    0xda, 0x02, // COM pins for 128x32
+    0x81, 0x8f, // Contrast
+    0xd9, 0xf1, // Precharge
+    0xdb, 0x40, // VCOMH deselect
+    0xa4, 0xa6, // Show RAM, not inverted
+    0xaf,       // Display on
+];
+
+pub struct Ssd1306 {
+    i2c: &'static mut crate::I2C,
+    buf: [u8; 1 + WIDTH * PAGES], // Data control byte, then the framebuffer
+    shown: String<CHARS>,
+}
+
+impl Ssd1306 {
+    // Initialize the display: on, horizontal addressing, blank
+    pub fn new(i2c: &'static mut crate::I2C) -> Ssd1306 {
+        let mut oled = Ssd1306 {
+            i2c,
+            buf: [0; 1 + WIDTH * PAGES],
+            shown: String::new(),
+        };
+        oled.buf[0] = 0x40;
+        oled.command(&INIT);
+        oled.flush();
+        oled
+    }
+    fn command(&mut self, cmds: &[u8]) {
+        for c in cmds {
+            // If the display is missing or broken there's nothing better to do
+            _ = self.i2c.blocking_write(ADDR, &[0x00, *c]);
+        }
+    }
+    fn flush(&mut self) {
+        self.command(&[0x21, 0, (WIDTH - 1) as u8, 0x22, 0, (PAGES - 1) as u8]);
+        _ = self.i2c.blocking_write(ADDR, &self.buf);
+    }
+    fn pixel(&mut self, x: i32, y: i32) {
+        if (0..WIDTH as i32).contains(&x) && (0..(PAGES * 8) as i32).contains(&y) {
+            self.buf[1 + x as usize + (y as usize / 8) * WIDTH] |= 1 << (y % 8);
+        }
+    }
+    // Draw a 2 pixel wide line
+    fn line(&mut self, (x0, y0): (i32, i32), (x1, y1): (i32, i32)) {
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).max(1);
+        for i in 0..=steps {
+            let x = x0 + (x1 - x0) * i / steps;
+            let y = y0 + (y1 - y0) * i / steps;
+            self.pixel(x, y);
+            self.pixel(x + 1, y);
+            self.pixel(x, y + 1);
+        }
+    }
+    fn draw_glyph(&mut self, x0: i32, g: Glyph) {
+        let y0 = 1;
+        let (w2, h2) = (CHAR_W / 2, CHAR_H / 2);
+        let points = [
+            (x0, y0),
+            (x0 + w2, y0),
+            (x0 + CHAR_W, y0),
+            (x0, y0 + h2),
+            (x0 + w2, y0 + h2),
+            (x0 + CHAR_W, y0 + h2),
+            (x0, y0 + CHAR_H),
+            (x0 + w2, y0 + CHAR_H),
+            (x0 + CHAR_W, y0 + CHAR_H),
+        ];
+        for (seg, (p0, p1)) in SEGMENT_LINES.iter().enumerate() {
+            if g & (1 << seg) != 0 {
+                self.line(points[*p0], points[*p1]);
+            }
+        }
+    }
+    // Show s, centered, if it isn't already
+    fn show(&mut self, args: core::fmt::Arguments) {
+        let s: String<CHARS> = display::format(args);
+        if s == self.shown {
+            return;
+        }
+        self.buf[1..].fill(0);
+        let n = s.chars().count() as i32;
+        let mut x = (WIDTH as i32 - n * CELL_W) / 2 + (CELL_W - CHAR_W) / 2;
+        for ch in s.chars() {
+            self.draw_glyph(x, glyph::font(ch));
+            x += CELL_W;
+        }
+        self.flush();
+        self.shown = s;
+    }
+}
+
+impl Display for Ssd1306 {
+    fn show_number(&mut self, n: u32) {
+        self.show(format_args!("{}", n));
+    }
+    fn show_text(&mut self, s: &str) {
+        self.show(format_args!("{}", s));
+    }
+    fn set_brightness(&mut self, level: u8) {
+        self.command(&[0x81, level]);
+    }
+}
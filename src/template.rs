@@ -0,0 +1,119 @@
+// template: Ensemble averaged pulse template
+//
+// The real-time version of the hair plots: every beat's waveform, aligned on
+// its detected peak, is averaged into a template.  The first TEMPLATE_BEATS
+// beats are a plain average, after that each new beat is blended in with
+// weight 1/TEMPLATE_BEATS, so the template effectively covers the last
+// TEMPLATE_BEATS beats without having to keep them all.
+//
+// Each beat is also correlated against the template before being added.  The
+// correlation is a per-beat quality score: close to 1 for a beat shaped like
+// the recent ones, low for noise or artifacts.  Poor beats are kept out of the
+// template once it is established.
+//
+// As with the APG, the window extends past where hr_alg3 reports the beat, so
+// samples are kept in a short history and the beat completes a little later.
+
+use libm::sqrt;
+use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
+
+const DECIMATE: usize = 4; // Template points are 4ms apart
+pub const HALF_MS: usize = 400; // Template spans +/- this around the peak
+pub const LEN: usize = 2 * HALF_MS / DECIMATE + 1;
+pub const MS_PER_POINT: usize = DECIMATE;
+const HISTORY: usize = 320; // Decimated samples kept, about 1.3s
+const TEMPLATE_BEATS: u32 = 16;
+const ADD_MIN: f64 = 0.6; // Correlation needed to join an established template
+
+pub struct Template {
+    history: ConstGenericRingBuffer<f64, HISTORY>,
+    sum: f64,      // Accumulates DECIMATE samples
+    pending: bool, // Waiting for the rest of a beat to arrive
+    peak_n: usize, // Sample number of the pending beat's peak
+    template: [f64; LEN],
+    beats: u32, // Beats averaged in so far
+}
+
+impl Template {
+    pub fn new() -> Template {
+        Template {
+            history: ConstGenericRingBuffer::<f64, HISTORY>::new(),
+            sum: 0.0,
+            pending: false,
+            peak_n: 0,
+            template: [0.0; LEN],
+            beats: 0,
+        }
+    }
+    // Schedule a beat peaking at sample number peak_n
+    pub fn beat(&mut self, peak_n: usize) {
+        self.peak_n = peak_n;
+        self.pending = true;
+    }
+    // Drop any pending beat, eg. because of crazy samples
+    pub fn cancel(&mut self) {
+        self.pending = false;
+    }
+    // Return the template (mean removed, counts) and the number of beats in it
+    pub fn template(&self) -> (&[f64; LEN], u32) {
+        (&self.template, self.beats.min(TEMPLATE_BEATS))
+    }
+    // Feed sample number n
+    // Return (peak_n, correlation with template) when a scheduled beat has
+    //   been completely seen.  Correlation is 0 until there is a template.
+    pub fn push(&mut self, n: usize, x: f64) -> Option<(usize, f64)> {
+        self.sum += x;
+        if (n + 1) % DECIMATE != 0 {
+            return None;
+        }
+        self.history.push(self.sum / DECIMATE as f64);
+        self.sum = 0.0;
+        if !self.pending || n < self.peak_n + HALF_MS {
+            return None;
+        }
+        self.pending = false;
+        // history[len - 1] ends at sample n
+        let back = (n + HALF_MS - self.peak_n) / DECIMATE + 1;
+        if back > self.history.len() {
+            return None;
+        }
+        let start = self.history.len() - back;
+        let mut beat = [0f64; LEN];
+        for (b, h) in beat.iter_mut().zip(self.history.iter().skip(start)) {
+            *b = *h;
+        }
+        let mean = beat.iter().sum::<f64>() / LEN as f64;
+        for b in beat.iter_mut() {
+            *b -= mean;
+        }
+
+        let corr = if self.beats > 0 {
+            correlate(&beat, &self.template)
+        } else {
+            0.0
+        };
+        if self.beats < TEMPLATE_BEATS || corr >= ADD_MIN {
+            self.beats += 1;
+            let w = 1.0 / self.beats.min(TEMPLATE_BEATS) as f64;
+            for (t, b) in self.template.iter_mut().zip(beat.iter()) {
+                *t += (b - *t) * w;
+            }
+        }
+        Some((self.peak_n, corr))
+    }
+}
+
+// Pearson correlation of two mean removed waveforms
+fn correlate(a: &[f64; LEN], b: &[f64; LEN]) -> f64 {
+    let (mut ab, mut aa, mut bb) = (0f64, 0f64, 0f64);
+    for (x, y) in a.iter().zip(b.iter()) {
+        ab += x * y;
+        aa += x * x;
+        bb += y * y;
+    }
+    if aa > 0.0 && bb > 0.0 {
+        ab / sqrt(aa * bb)
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,278 @@
+// trend: HR trend and heart rate recovery
+//
+// Keeps the tracked HR averaged over POINT_MS at a time, for the last
+// POINTS points (30 minutes), and works out trends from that:
+//   * Slope over any window, by least squares, in BPM per minute
+//   * Exercise peaks: a maximum at least RISE_BPM above the lowest HR before
+//     it, confirmed once HR has dropped DROP_BPM below it
+//   * Heart rate recovery (HRR): how far HR has dropped 1 and 2 minutes after
+//     an exercise peak, from the first point with HR at or after each
+// Points with no HR (sensor off, not locked) are kept as 0 and skipped.  After
+// LOST_POINTS of them in a row, the peak search starts over, so a low from
+// before the gap can't pair with a high after it.
+
+use heapless::Deque;
+
+const POINT_MS: usize = 5000;
+const POINTS: usize = 360; // 30 minutes
+const RISE_BPM: f64 = 30.0;
+const DROP_BPM: f64 = 10.0;
+const HRR1_POINTS: usize = 60000 / POINT_MS;
+const HRR2_POINTS: usize = 120000 / POINT_MS;
+const MIN_SLOPE_POINTS: usize = 3;
+const LOST_POINTS: usize = 60000 / POINT_MS;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrendEvent {
+    Peak(f64), // Exercise peak HR
+    Hrr1(f64), // Drop 1 minute after the peak
+    Hrr2(f64), // Drop 2 minutes after the peak
+}
+
+pub struct Trend {
+    points: Deque<f64, POINTS>,
+    count: usize, // Points seen since boot; the newest is count - 1
+    sum: f64,     // Accumulating current point
+    sum_n: usize,
+    lo: f64, // Lowest HR since the peak search started
+    hi: f64, // Highest HR since lo, latest if several
+    hi_ix: usize,
+    peak: Option<(usize, f64)>, // Confirmed peak waiting for recovery
+    hrr1_sent: bool,            // Hrr1 reported for this peak; Hrr2 ends it
+    lost: usize,                // Points in a row with no HR
+}
+
+impl Trend {
+    pub fn new() -> Trend {
+        Trend {
+            points: Deque::new(),
+            count: 0,
+            sum: 0.0,
+            sum_n: 0,
+            lo: 0.0,
+            hi: 0.0,
+            hi_ix: 0,
+            peak: None,
+            hrr1_sent: false,
+            lost: 0,
+        }
+    }
+    // Feed sample number n with tracked HR bpm, 0 if none
+    // Return any trend event from a completed point
+    pub fn tick(&mut self, n: usize, bpm: f64) -> Option<TrendEvent> {
+        if bpm > 0.0 {
+            self.sum += bpm;
+            self.sum_n += 1;
+        }
+        if (n + 1) % POINT_MS != 0 {
+            return None;
+        }
+        // Most of the point must have had HR for it to count
+        let p = if self.sum_n > POINT_MS / 2 {
+            self.sum / self.sum_n as f64
+        } else {
+            0.0
+        };
+        self.sum = 0.0;
+        self.sum_n = 0;
+        if self.points.is_full() {
+            self.points.pop_front();
+        }
+        _ = self.points.push_back(p);
+        self.count += 1;
+        if p > 0.0 {
+            self.lost = 0;
+            self.update(self.count - 1, p)
+        } else {
+            self.lost += 1;
+            if self.lost == LOST_POINTS {
+                self.lo = 0.0;
+                self.hi = 0.0;
+                self.hi_ix = 0;
+            }
+            None
+        }
+    }
+    fn update(&mut self, ix: usize, p: f64) -> Option<TrendEvent> {
+        if let Some((peak_ix, peak)) = self.peak {
+            if p > peak {
+                // Still climbing after all
+                self.peak = None;
+                self.hi = p;
+                self.hi_ix = ix;
+                return None;
+            }
+            // One event per point, so if both are due Hrr2 waits for the next
+            if !self.hrr1_sent && ix - peak_ix >= HRR1_POINTS {
+                self.hrr1_sent = true;
+                return Some(TrendEvent::Hrr1(peak - self.recovery(peak_ix + HRR1_POINTS, p)));
+            }
+            if ix - peak_ix >= HRR2_POINTS {
+                self.peak = None;
+                self.lo = p;
+                self.hi = p;
+                self.hi_ix = ix;
+                return Some(TrendEvent::Hrr2(peak - self.recovery(peak_ix + HRR2_POINTS, p)));
+            }
+            return None;
+        }
+        if self.lo == 0.0 || p < self.lo {
+            self.lo = p;
+            self.hi = p;
+            self.hi_ix = ix;
+        } else if p >= self.hi {
+            // Latest of equal highs, so recovery times from the end of a plateau
+            self.hi = p;
+            self.hi_ix = ix;
+        }
+        if self.hi - self.lo >= RISE_BPM && self.hi - p >= DROP_BPM {
+            // Recovery may already be a minute in by the time the drop shows,
+            //   in which case the HRR events follow on the next points
+            self.peak = Some((self.hi_ix, self.hi));
+            self.hrr1_sent = false;
+            return Some(TrendEvent::Peak(self.hi));
+        }
+        None
+    }
+    // Return the first point with HR from ix on, or p (the newest) if none
+    fn recovery(&self, ix: usize, p: f64) -> f64 {
+        (ix..self.count).map(|i| self.point(i)).find(|x| *x > 0.0).unwrap_or(p)
+    }
+    // Return point ix, counting since boot, or 0 if no longer kept
+    fn point(&self, ix: usize) -> f64 {
+        let first = self.count - self.points.len();
+        if ix < first {
+            return 0.0;
+        }
+        self.points.iter().nth(ix - first).copied().unwrap_or(0.0)
+    }
+    // Return HR slope over the last window_ms, BPM per minute, if there are
+    //   enough points with HR to tell
+    pub fn slope(&self, window_ms: usize) -> Option<f64> {
+        let k = (window_ms / POINT_MS).min(self.points.len());
+        let skip = self.points.len() - k;
+        let (mut n, mut sx, mut sy, mut sxx, mut sxy) = (0usize, 0f64, 0f64, 0f64, 0f64);
+        for (i, p) in self.points.iter().skip(skip).enumerate() {
+            if *p > 0.0 {
+                let x = i as f64;
+                n += 1;
+                sx += x;
+                sy += p;
+                sxx += x * x;
+                sxy += x * p;
+            }
+        }
+        if n < MIN_SLOPE_POINTS {
+            return None;
+        }
+        let nf = n as f64;
+        let d = nf * sxx - sx * sx;
+        if d == 0.0 {
+            return None;
+        }
+        let per_point = (nf * sxy - sx * sy) / d;
+        Some(per_point * (60000 / POINT_MS) as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Warm up at 70, ramp to 160 over 5 minutes, hold 2 minutes, then recover
+    //   quickly to 130 in the first minute and 110 in the second
+    fn profile(ms: usize) -> f64 {
+        let s = ms as f64 / 1000.0;
+        if s < 120.0 {
+            70.0
+        } else if s < 420.0 {
+            70.0 + 90.0 * (s - 120.0) / 300.0
+        } else if s < 540.0 {
+            160.0
+        } else if s < 600.0 {
+            160.0 - 30.0 * (s - 540.0) / 60.0
+        } else if s < 660.0 {
+            130.0 - 20.0 * (s - 600.0) / 60.0
+        } else {
+            110.0
+        }
+    }
+
+    fn events(f: impl Fn(usize) -> f64) -> heapless::Vec<TrendEvent, 8> {
+        let mut trend = Trend::new();
+        let mut events = heapless::Vec::new();
+        for n in 0..900_000 {
+            if let Some(e) = trend.tick(n, f(n)) {
+                events.push(e).unwrap();
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn slope_and_recovery() {
+        let mut trend = Trend::new();
+        let mut events = heapless::Vec::<TrendEvent, 8>::new();
+        for n in 0..900_000 {
+            if let Some(e) = trend.tick(n, profile(n)) {
+                events.push(e).unwrap();
+            }
+            if n == 400_000 {
+                let slope = trend.slope(60000).unwrap();
+                assert!((slope - 18.0).abs() < 0.5);
+            }
+        }
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], TrendEvent::Peak(p) if (p - 160.0).abs() < 0.5));
+        // The peak is the last point at 160, about 540s
+        assert!(matches!(events[1], TrendEvent::Hrr1(d) if (d - 30.0).abs() < 3.0));
+        assert!(matches!(events[2], TrendEvent::Hrr2(d) if (d - 50.0).abs() < 3.0));
+        assert!(trend.slope(60000).unwrap().abs() < 0.1);
+    }
+
+    #[test]
+    fn late_peak() {
+        // A slow decline only drops DROP_BPM 90s after the peak, when the
+        //   1 minute point is already past
+        let events = events(|n| {
+            if n < 540_000 {
+                profile(n)
+            } else {
+                160.0 - (n - 540_000) as f64 / 9000.0
+            }
+        });
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], TrendEvent::Peak(p) if (p - 160.0).abs() < 0.5));
+        assert!(matches!(events[1], TrendEvent::Hrr1(d) if (d - 6.4).abs() < 1.0));
+        assert!(matches!(events[2], TrendEvent::Hrr2(d) if (d - 13.1).abs() < 1.0));
+    }
+
+    #[test]
+    fn dropped_points() {
+        // No HR from 590s to 605s covers the 1 minute point, so HRR1 comes
+        //   from the next point instead, at about 607.5s
+        let events = events(|n| {
+            if (590_000..605_000).contains(&n) {
+                0.0
+            } else {
+                profile(n)
+            }
+        });
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[1], TrendEvent::Hrr1(d) if (d - 32.5).abs() < 1.0));
+        assert!(matches!(events[2], TrendEvent::Hrr2(d) if (d - 50.0).abs() < 3.0));
+    }
+
+    #[test]
+    fn sensor_off() {
+        // Resting at 70, then the sensor is off for 5 minutes, and back on
+        //   at 110 that drops to 95.  That's no exercise peak
+        let events = events(|n| match n {
+            0..=119_999 => 70.0,
+            120_000..=419_999 => 0.0,
+            420_000..=599_999 => 110.0,
+            _ => 95.0,
+        });
+        assert!(events.is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+// zones: Training zones and HR alarms
+//
+// Zones use the Karvonen (heart rate reserve) method: zone k starts at
+//
+//     rest + ZONE_FRACS[k-1] * (max - rest)
+//
+// with max either entered directly or predicted from age.  Zone 0 is anything
+// below zone 1.  Alarms fire when HR goes below or above fixed limits.
+//
+// Everything is evaluated on the tracked (smoothed) HR, and both zones and
+// alarms need to be outside a hysteresis band for a minimum time before they
+// change, so one odd beat doesn't flip anything.  Changes are returned as
+// events for the caller to show.  Alarms stay on until a valid HR clears them,
+// so losing the sensor doesn't silence one.
+
+const ZONE_FRACS: [f64; 5] = [0.5, 0.6, 0.7, 0.8, 0.9]; // Of HR reserve
+pub const ZONES: usize = ZONE_FRACS.len() + 1;
+const HYST_BPM: f64 = 2.0; // Must be this far back past a boundary to return
+const MIN_ZONE_MS: usize = 5000; // New zone must hold this long
+const MIN_ALARM_MS: usize = 10000; // Alarm condition must hold this long, on or off
+const BLINK_FAST_MS: usize = 125; // LED pattern half periods
+const BLINK_SLOW_MS: usize = 500;
+
+#[derive(Clone, Copy)]
+pub struct ZoneConfig {
+    pub age: u32,      // Used to predict max HR if max_hr is 0
+    pub max_hr: f64,   // BPM, or 0 to use 220 - age
+    pub rest_hr: f64,  // BPM
+    pub alarm_lo: f64, // BPM, alarm below this
+    pub alarm_hi: f64, // BPM, alarm above this
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Alarm {
+    Low,
+    High,
+}
+
+impl Alarm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Alarm::Low => "low",
+            Alarm::High => "high",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ZoneEvent {
+    Zone(u8),
+    AlarmOn(Alarm),
+    AlarmOff(Alarm),
+}
+
+pub type ZoneEvents = heapless::Vec<ZoneEvent, 3>;
+
+// A boolean state that only changes after its condition holds for a while
+struct Debounce {
+    on: bool,
+    since: Option<usize>, // When the condition to change started holding
+    min_ms: usize,
+}
+
+impl Debounce {
+    fn new(min_ms: usize) -> Debounce {
+        Debounce {
+            on: false,
+            since: None,
+            min_ms,
+        }
+    }
+    // Return the new state if it changed
+    fn update(&mut self, n: usize, set: bool, clear: bool) -> Option<bool> {
+        let change = if self.on { clear } else { set };
+        if !change {
+            self.since = None;
+            return None;
+        }
+        let since = *self.since.get_or_insert(n);
+        if n - since < self.min_ms {
+            return None;
+        }
+        self.on = !self.on;
+        self.since = None;
+        Some(self.on)
+    }
+}
+
+pub struct Zones {
+    cfg: ZoneConfig,
+    bounds: [f64; ZONES - 1], // BPM where each zone above 0 starts
+    zone: u8,
+    candidate: u8, // Zone the HR has been in since candidate_n
+    candidate_n: usize,
+    low: Debounce,
+    high: Debounce,
+}
+
+impl Zones {
+    pub fn new(cfg: ZoneConfig) -> Zones {
+        let max_hr = if cfg.max_hr > 0.0 {
+            cfg.max_hr
+        } else {
+            220.0 - cfg.age as f64
+        };
+        let mut bounds = [0f64; ZONES - 1];
+        for (b, f) in bounds.iter_mut().zip(ZONE_FRACS.iter()) {
+            *b = cfg.rest_hr + f * (max_hr - cfg.rest_hr);
+        }
+        Zones {
+            cfg,
+            bounds,
+            zone: 0,
+            candidate: 0,
+            candidate_n: 0,
+            low: Debounce::new(MIN_ALARM_MS),
+            high: Debounce::new(MIN_ALARM_MS),
+        }
+    }
+    // Forget half-timed changes, eg. when HR stopped being tracked
+    pub fn restart(&mut self) {
+        self.candidate = self.zone;
+        self.low.since = None;
+        self.high.since = None;
+    }
+    // Evaluate tracked HR bpm at sample number n
+    pub fn update(&mut self, n: usize, bpm: f64) -> ZoneEvents {
+        let mut events = ZoneEvents::new();
+
+        let mut z = self.bounds.iter().filter(|b| bpm >= **b).count() as u8;
+        if z < self.zone && bpm >= self.bounds[self.zone as usize - 1] - HYST_BPM {
+            z = self.zone; // Not far enough below this zone to leave it
+        }
+        if z == self.zone || z != self.candidate {
+            self.candidate = z;
+            self.candidate_n = n;
+        } else if n - self.candidate_n >= MIN_ZONE_MS {
+            self.zone = z;
+            _ = events.push(ZoneEvent::Zone(z));
+        }
+
+        let lo = self.cfg.alarm_lo;
+        if let Some(on) = self.low.update(n, bpm < lo, bpm > lo + HYST_BPM) {
+            _ = events.push(if on {
+                ZoneEvent::AlarmOn(Alarm::Low)
+            } else {
+                ZoneEvent::AlarmOff(Alarm::Low)
+            });
+        }
+        let hi = self.cfg.alarm_hi;
+        if let Some(on) = self.high.update(n, bpm > hi, bpm < hi - HYST_BPM) {
+            _ = events.push(if on {
+                ZoneEvent::AlarmOn(Alarm::High)
+            } else {
+                ZoneEvent::AlarmOff(Alarm::High)
+            });
+        }
+        events
+    }
+    pub fn zone(&self) -> u8 {
+        self.zone
+    }
+    // Return (lowest, highest) BPM of zone z; highest is 0 for the top zone
+    pub fn zone_range(&self, z: u8) -> (f64, f64) {
+        let z = z as usize;
+        let lo = if z == 0 { 0.0 } else { self.bounds[z - 1] };
+        let hi = if z < ZONES - 1 { self.bounds[z] } else { 0.0 };
+        (lo, hi)
+    }
+    pub fn alarm(&self) -> Option<Alarm> {
+        if self.high.on {
+            Some(Alarm::High)
+        } else if self.low.on {
+            Some(Alarm::Low)
+        } else {
+            None
+        }
+    }
+    // LED pattern at sample number n: (LED1, LED3), or None if no alarm
+    //   High: both blink fast together; Low: they alternate slowly
+    pub fn leds(&self, n: usize) -> Option<(bool, bool)> {
+        match self.alarm()? {
+            Alarm::High => {
+                let on = (n / BLINK_FAST_MS) % 2 == 0;
+                Some((on, on))
+            }
+            Alarm::Low => {
+                let on = (n / BLINK_SLOW_MS) % 2 == 0;
+                Some((on, !on))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: ZoneConfig = ZoneConfig {
+        age: 40,
+        max_hr: 0.0,
+        rest_hr: 60.0,
+        alarm_lo: 40.0,
+        alarm_hi: 170.0,
+    };
+
+    // Feed a beat every 500ms at bpm for ms, returning all events
+    fn run(zones: &mut Zones, n: &mut usize, bpm: f64, ms: usize) -> heapless::Vec<ZoneEvent, 16> {
+        let mut all = heapless::Vec::new();
+        let end = *n + ms;
+        while *n < end {
+            *n += 500;
+            for e in zones.update(*n, bpm) {
+                all.push(e).unwrap();
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn karvonen() {
+        let zones = Zones::new(CFG);
+        // Max 180, reserve 120
+        assert_eq!(zones.zone_range(0), (0.0, 120.0));
+        assert_eq!(zones.zone_range(3), (144.0, 156.0));
+        assert_eq!(zones.zone_range(5), (168.0, 0.0));
+    }
+
+    #[test]
+    fn hysteresis_and_timers() {
+        let mut zones = Zones::new(CFG);
+        let mut n = 0;
+        // A short burst doesn't change zone
+        assert!(run(&mut zones, &mut n, 150.0, 3000).is_empty());
+        assert!(run(&mut zones, &mut n, 100.0, 10000).is_empty());
+        // Holding it does
+        assert_eq!(run(&mut zones, &mut n, 150.0, 10000)[0], ZoneEvent::Zone(3));
+        // Just under the boundary stays in the zone, well under leaves it
+        assert!(run(&mut zones, &mut n, 143.0, 10000).is_empty());
+        assert_eq!(run(&mut zones, &mut n, 141.0, 10000)[0], ZoneEvent::Zone(2));
+
+        // High alarm needs to hold for longer, and clear past the hysteresis
+        let e = run(&mut zones, &mut n, 175.0, 20000);
+        assert_eq!(e.as_slice(), &[ZoneEvent::Zone(5), ZoneEvent::AlarmOn(Alarm::High)]);
+        assert_eq!(zones.alarm(), Some(Alarm::High));
+        assert!(run(&mut zones, &mut n, 169.0, 20000).is_empty());
+        let e = run(&mut zones, &mut n, 160.0, 20000);
+        assert!(e.contains(&ZoneEvent::AlarmOff(Alarm::High)));
+        assert_eq!(zones.alarm(), None);
+    }
+}